@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use quarto::agent::{random_playout, Agent, MinimaxAgent, MonteCarloAgent};
+use quarto::game::{Game, Phase, Rules};
+use quarto::position::parse_position;
+use quarto::{find_quarto, shared_attribute, unplayed_pieces, winning_squares, Piece, Player};
+
+/// Seven pieces placed with no line complete, and a piece staged
+fn midgame() -> Game {
+    let board = parse_position("0..6/.a5./3..e/.2..").unwrap();
+
+    let staged = Piece::from_index(15).unwrap();
+    let pieces = unplayed_pieces(&board)
+        .into_iter()
+        .filter(|piece| *piece != staged)
+        .collect();
+
+    Game::from_position(
+        board,
+        pieces,
+        Phase::AwaitingPlacement(Player::A, staged),
+        Rules::default(),
+    )
+    .expect("every piece is on the board, staged or in the pool")
+}
+
+fn win_detection(c: &mut Criterion) {
+    let game = midgame();
+    let row = [
+        game.board()[0][0].as_ref(),
+        game.board()[1][1].as_ref(),
+        game.board()[2][2].as_ref(),
+        game.board()[3][3].as_ref(),
+    ];
+    let staged = game.staged().cloned().unwrap();
+
+    c.bench_function("shared_attribute", |b| {
+        b.iter(|| shared_attribute(black_box(&row)))
+    });
+    c.bench_function("find_quarto", |b| {
+        b.iter(|| find_quarto(black_box(game.board())))
+    });
+    c.bench_function("winning_squares", |b| {
+        b.iter(|| winning_squares(black_box(game.board()), black_box(&staged)))
+    });
+}
+
+fn playouts(c: &mut Criterion) {
+    let game = midgame();
+    let mut rng = StdRng::seed_from_u64(1);
+
+    c.bench_function("random_playout", |b| {
+        b.iter(|| random_playout(game.clone(), &mut rng))
+    });
+}
+
+fn move_selection(c: &mut Criterion) {
+    let game = midgame();
+    let staged = game.staged().cloned().unwrap();
+    let mut group = c.benchmark_group("choose_placement");
+    group.sample_size(10);
+
+    for depth in 1..=2 {
+        group.bench_with_input(BenchmarkId::new("minimax", depth), &depth, |b, depth| {
+            let mut agent = MinimaxAgent {
+                depth: *depth,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                nodes: 0,
+                on_progress: None,
+                rng: StdRng::seed_from_u64(1),
+            };
+            b.iter(|| agent.choose_placement(&game, &staged))
+        });
+    }
+
+    for playouts in [50, 200].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("montecarlo", playouts),
+            playouts,
+            |b, playouts| {
+                let mut agent = MonteCarloAgent {
+                    playouts: *playouts,
+                    time_budget: None,
+                    analysis: None,
+                    cancel: None,
+                    rng: StdRng::seed_from_u64(1),
+                };
+                b.iter(|| agent.choose_placement(&game, &staged))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, win_detection, playouts, move_selection);
+criterion_main!(benches);
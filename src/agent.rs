@@ -0,0 +1,2008 @@
+//! Players that can drive a `Game`, whether a person at the terminal or
+//! one of the search strategies.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::iter;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::analysis::{MoveAnalysis, ScoredMove};
+use crate::endgame;
+use crate::game::{parse_square, square_name, Game, Move, Phase, Resolution, Rules, Threat};
+use crate::logging;
+use crate::tray::Tray;
+use crate::{Attribute, Coord, Piece, Player};
+
+/// Both choices return `None` to resign the game instead of moving.
+pub trait Agent {
+    /// Pick an empty square for the staged `piece`
+    fn choose_placement(&mut self, game: &Game, piece: &Piece) -> Option<Coord>;
+
+    /// Pick one of the remaining pieces for the opponent to place
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece>;
+
+    /// Asked after a choice of `None`: whether the agent meant to take
+    /// back its last turn rather than resign
+    fn wants_take_back(&mut self) -> bool {
+        false
+    }
+
+    /// Whether to call a quarto waiting to be called, under
+    /// `Rules::call_quarto`. Asked before each choice and again after a
+    /// choice of `None`.
+    fn calls_quarto(&mut self) -> bool {
+        true
+    }
+
+    /// Told before a choice in a timed game how long to spend on it.
+    /// Agents that don't search can ignore it.
+    fn set_time_budget(&mut self, _budget: Duration) {}
+
+    /// The reasoning behind the agent's last choice, for agents that
+    /// search
+    fn analysis(&self) -> Option<&MoveAnalysis> {
+        None
+    }
+
+    /// Told how a game it played as `player` ended, for agents that learn
+    /// from it
+    fn game_over(&mut self, _game: &Game, _player: Player) {}
+
+    /// Where an agent that learns from its games stands after them, for
+    /// the front end to show
+    fn standing(&self) -> Option<String> {
+        None
+    }
+
+    /// Threats the agent has announced to its opponent since last asked,
+    /// for the front end to show
+    fn take_announced_threats(&mut self) -> Vec<Threat> {
+        Vec::new()
+    }
+
+    /// Have `observer` told how each search goes while it runs. Agents that
+    /// don't search in steps can ignore it.
+    fn on_progress(&mut self, _observer: ProgressObserver) {}
+
+    /// Stop any search once `token` is cancelled, choosing `None`. Agents
+    /// that choose straight away can ignore it.
+    fn set_cancel(&mut self, _token: CancelToken) {}
+
+    /// Whether a choice of `None` came from a cancelled search, rather than
+    /// meaning to resign
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Cancels the searches of every agent given a clone of it, from whichever
+/// thread holds one. A token stays cancelled, so agents are given a fresh
+/// one to search again.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How far a search has got, told after each depth it finishes
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchProgress {
+    /// Depth of the search just finished
+    pub depth: usize,
+    /// Positions searched for this choice so far
+    pub nodes: u64,
+    /// What the search would choose if it stopped now
+    pub best_so_far: Option<Move>,
+    /// Roughly how much longer the search has to go: zero once it's done,
+    /// or `None` until it has taken long enough to tell
+    pub eta: Option<Duration>,
+}
+
+impl SearchProgress {
+    pub fn is_done(&self) -> bool {
+        self.eta == Some(Duration::from_secs(0))
+    }
+}
+
+impl fmt::Display for SearchProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Depth {}, {} positions", self.depth, self.nodes)?;
+        if let Some(best) = &self.best_so_far {
+            write!(f, ", best so far {}", best)?;
+        }
+        match self.eta {
+            Some(eta) if !self.is_done() => write!(f, ", about {:.1}s to go", eta.as_secs_f64()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Told of each `SearchProgress` as it's made
+pub type ProgressObserver = Box<dyn FnMut(&SearchProgress) + Send>;
+
+/// Names accepted by `engine_agent`
+pub const ENGINE_AGENTS: &[&str] = &["random", "montecarlo", "minimax"];
+
+/// Build one of the engine agents by name. Agents built with the same
+/// seed make the same choices in the same positions.
+pub fn engine_agent(name: &str, seed: u64) -> Option<Box<dyn Agent>> {
+    let rng = StdRng::seed_from_u64(seed);
+
+    match name {
+        "random" => Some(Box::new(RandomAgent { rng })),
+        "montecarlo" => Some(Box::new(MonteCarloAgent {
+            playouts: 200,
+            time_budget: None,
+            analysis: None,
+            cancel: None,
+            rng,
+        })),
+        "minimax" => Some(Box::new(MinimaxAgent {
+            depth: 2,
+            time_budget: None,
+            analysis: None,
+            cancel: None,
+            nodes: 0,
+            on_progress: None,
+            rng,
+        })),
+        _ => None,
+    }
+}
+
+/// Reads moves from standard input, resigning once it runs out
+#[derive(Clone)]
+pub struct HumanAgent {
+    /// Ask for confirmation before handing over a piece that wins on the
+    /// spot
+    pub training: bool,
+    /// List lines one piece short of a quarto alongside the board
+    pub show_threats: bool,
+    /// Show the board with the piece placed and ask before committing
+    pub confirm_placements: bool,
+    /// List the moves so far above the board
+    pub show_log: bool,
+    /// Show the engine's estimate of the chance of winning
+    pub show_eval: bool,
+    /// Count the pieces of each attribute value left and placed, beside
+    /// the tray
+    pub show_census: bool,
+    /// Draw placed pieces face down, all but the last one placed, so the
+    /// board has to be remembered
+    pub blind: bool,
+    /// Looks at the whole board left while playing blind
+    pub peeks: usize,
+    /// How the unplayed pieces are laid out. Any filter lasts until the
+    /// next piece is handed over.
+    pub tray: Tray,
+    /// Set when the last `None` was a request to take back a turn
+    take_back: bool,
+    /// Set when the last `None` was a call of quarto
+    calling: bool,
+    /// Drives the evaluation bar's playouts
+    pub rng: StdRng,
+}
+
+/// What a command at a prompt means for the choice being asked for
+enum Outcome {
+    /// Give the choice up, to resign, take back a turn or call quarto
+    Withdraw,
+    /// Ask for the choice again
+    Reprompt,
+}
+
+/// Playouts behind the evaluation bar, kept low so it shows up quickly
+const EVAL_PLAYOUTS: usize = 200;
+
+impl HumanAgent {
+    /// A player with every aid turned off, whose evaluation bar is seeded
+    /// with `seed`
+    pub fn new(seed: u64) -> Self {
+        HumanAgent {
+            training: false,
+            show_threats: false,
+            confirm_placements: false,
+            show_log: false,
+            show_eval: false,
+            show_census: false,
+            blind: false,
+            peeks: 0,
+            tray: Tray::default(),
+            take_back: false,
+            calling: false,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Handle a request to resign or take back a turn, returning whether
+    /// to give up the current choice
+    fn withdraw(&mut self, game: &Game, input: &str) -> bool {
+        match input.trim() {
+            "resign" => confirm("Resign this game?"),
+            "undo" if game.can_take_back() => {
+                self.take_back = true;
+                true
+            }
+            "undo" => {
+                println!("There is no turn you can take back");
+                false
+            }
+            "quarto" if game.uncalled_quarto().is_some() => {
+                self.calling = true;
+                true
+            }
+            "quarto" => {
+                println!("There is no quarto to call");
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Carry out a command both prompts take: resigning, taking back a
+    /// turn, calling quarto, peeking, inspecting a piece or laying out the
+    /// tray. `None` if `input` isn't one, so it's a square or piece.
+    fn handle_command(&mut self, game: &Game, input: &str) -> Option<Outcome> {
+        match split_command(input) {
+            ("resign" | "undo" | "quarto", "") => {
+                if self.withdraw(game, input) {
+                    return Some(Outcome::Withdraw);
+                }
+            }
+            ("peek", "") => self.peek(game),
+            ("inspect", piece) => inspect(game, piece, self.blind),
+            ("group" | "only", _) => match self.tray.command(input) {
+                Ok(()) => self.show(game),
+                Err(err) => println!("{}", err),
+            },
+            _ => return None,
+        }
+
+        Some(Outcome::Reprompt)
+    }
+
+    /// Show the board face up, if any peeks are left
+    fn peek(&mut self, game: &Game) {
+        if !self.blind {
+            println!("The board is already face up");
+        } else if self.peeks == 0 {
+            println!("You have no peeks left");
+        } else {
+            self.peeks -= 1;
+            println!("{}", game);
+            println!("{} peeks left", self.peeks);
+        }
+    }
+
+    fn show(&mut self, game: &Game) {
+        if self.show_log {
+            for entry in game.move_log() {
+                println!("{}", entry);
+            }
+            println!();
+        }
+
+        println!("{}", game.arranged(&self.tray, self.blind));
+
+        if self.show_census {
+            println!("{:<7} left  placed", "");
+            for count in game.attribute_census() {
+                println!(
+                    "{:<7} {:>4}  {:>6}",
+                    count.attribute.values()[count.value],
+                    count.unplayed,
+                    count.placed
+                );
+            }
+            println!();
+        }
+
+        if self.show_eval {
+            if let Some(player) = game.current_player() {
+                let chance = game.evaluate(player, EVAL_PLAYOUTS, &mut self.rng);
+                let filled = (chance * 20.0).round() as usize;
+                println!(
+                    "[{}{}] {:.0}% to win",
+                    "#".repeat(filled),
+                    "-".repeat(20 - filled),
+                    chance * 100.0
+                );
+            }
+        }
+
+        if self.show_threats && !self.blind {
+            for threat in game.threats() {
+                println!("Threat: {}", threat);
+            }
+            if let Some(piece) = game.fork() {
+                println!("Fork: giving {:x} wins by force", piece.index());
+            }
+        }
+    }
+}
+
+/// Name a piece's attributes and where it would complete a quarto, or only
+/// its attributes when playing `blind`
+fn inspect(game: &Game, text: &str, blind: bool) {
+    let piece = match Piece::parse(text) {
+        Some(piece) => piece,
+        None => {
+            println!("Expected a piece 0-f to inspect");
+            return;
+        }
+    };
+
+    println!(
+        "{:x}: {} {}, {} {}, {} {}, {} {}",
+        piece.index(),
+        Attribute::Height,
+        piece.height(),
+        Attribute::Color,
+        piece.color(),
+        Attribute::Density,
+        piece.density(),
+        Attribute::Shape,
+        piece.shape()
+    );
+    if blind {
+        return;
+    }
+
+    let placed = (0..4)
+        .flat_map(|row_idx| (0..4).map(move |square_idx| (row_idx, square_idx)))
+        .find(|(row_idx, square_idx)| game.board()[*row_idx][*square_idx].as_ref() == Some(&piece));
+    if let Some((row_idx, square_idx)) = placed {
+        println!("It's on {}", square_name(row_idx, square_idx));
+        return;
+    }
+
+    let lines = game.completing_lines(&piece);
+    if lines.is_empty() {
+        println!("It completes no line");
+    }
+    for threat in lines {
+        println!("It completes {}", threat);
+    }
+}
+
+/// Split a command like `inspect 7` into its first word and the rest
+fn split_command(input: &str) -> (&str, &str) {
+    let input = input.trim();
+
+    match input.split_once(char::is_whitespace) {
+        Some((command, rest)) => (command, rest.trim()),
+        None => (input, ""),
+    }
+}
+
+/// The ways out of a turn the prompts should offer
+fn withdrawals(game: &Game) -> &'static str {
+    match (game.rules().take_backs > 0, game.rules().call_quarto) {
+        (false, false) => "resign",
+        (true, false) => "undo or resign",
+        (false, true) => "quarto or resign",
+        (true, true) => "quarto, undo or resign",
+    }
+}
+
+impl Agent for HumanAgent {
+    fn choose_placement(&mut self, game: &Game, _piece: &Piece) -> Option<Coord> {
+        self.show(game);
+
+        loop {
+            // With no more input there's no move coming, so resign
+            let input = prompt(&format!(
+                "Square to place on (e.g. b3), or {}: ",
+                withdrawals(game)
+            ))?;
+            match self.handle_command(game, &input) {
+                Some(Outcome::Withdraw) => return None,
+                Some(Outcome::Reprompt) => continue,
+                None => {}
+            }
+
+            let (row_idx, square_idx) = match parse_square(&input) {
+                Some(square) if game.legal_squares().contains(&square) => square,
+                Some(square) if game.board()[square.0][square.1].is_none() => {
+                    println!("That square is next to the last piece placed");
+                    continue;
+                }
+                Some(_) => {
+                    println!("That square is taken");
+                    continue;
+                }
+                None => {
+                    println!("Expected a column a-d followed by a row 1-4, or inspect <piece>");
+                    continue;
+                }
+            };
+
+            if self.confirm_placements {
+                let preview = game
+                    .child(Move::Place((row_idx, square_idx)))
+                    .expect("the square was checked to be legal");
+                if self.blind {
+                    println!("{}", preview.blindfolded());
+                } else {
+                    println!("{}", preview);
+                }
+
+                let question = format!("Place it on {}?", square_name(row_idx, square_idx));
+                if !confirm(&question) {
+                    continue;
+                }
+            }
+
+            return Some((row_idx, square_idx));
+        }
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        self.show(game);
+
+        loop {
+            let input = prompt(&format!(
+                "Piece to give your opponent (0-f), or {}: ",
+                withdrawals(game)
+            ))?;
+            match self.handle_command(game, &input) {
+                Some(Outcome::Withdraw) => return None,
+                Some(Outcome::Reprompt) => continue,
+                None => {}
+            }
+
+            let piece = Piece::parse(&input)
+                .and_then(|piece| game.pieces().iter().find(|remaining| **remaining == piece));
+
+            let piece = match piece {
+                Some(piece) => piece,
+                None => {
+                    println!(
+                        "Expected one of the remaining pieces, inspect <piece>, group <attribute> or only <value>"
+                    );
+                    continue;
+                }
+            };
+
+            if self.training {
+                if let Some((row_idx, square_idx)) = game.loses_immediately(piece) {
+                    let question = format!(
+                        "This piece wins for your opponent on {}. Hand it over anyway?",
+                        square_name(row_idx, square_idx)
+                    );
+                    if !confirm(&question) {
+                        continue;
+                    }
+                }
+            }
+
+            self.tray.filter = None;
+            return Some(piece.clone());
+        }
+    }
+
+    fn wants_take_back(&mut self) -> bool {
+        std::mem::replace(&mut self.take_back, false)
+    }
+
+    fn calls_quarto(&mut self) -> bool {
+        std::mem::replace(&mut self.calling, false)
+    }
+}
+
+/// Ask a yes or no question, defaulting to no, as it does when input
+/// runs out
+pub fn confirm(question: &str) -> bool {
+    let answer = prompt(&format!("{} [y/N] ", question));
+
+    matches!(answer.as_deref().map(str::trim), Some("y" | "Y" | "yes"))
+}
+
+/// Print `message` and read a line of input, or `None` once input runs
+/// out or can't be read
+pub fn prompt(message: &str) -> Option<String> {
+    print!("{}", message);
+    io::stdout().flush().expect("failed to flush stdout");
+
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+/// An engine agent held back to give a weaker opponent a chance
+pub struct HandicappedAgent {
+    pub inner: Box<dyn Agent>,
+    /// Announce the threats on the board after each placement
+    pub announce_threats: bool,
+    /// Stage the first piece at random rather than choosing it
+    pub random_first_stage: bool,
+    pub rng: StdRng,
+    /// Threats announced with the last placement, until they're taken
+    pub announced: Vec<Threat>,
+}
+
+impl Agent for HandicappedAgent {
+    fn choose_placement(&mut self, game: &Game, piece: &Piece) -> Option<Coord> {
+        let choice = self.inner.choose_placement(game, piece)?;
+
+        if self.announce_threats {
+            let child = game.child(Move::Place(choice));
+            if let Some(child) = child.ok().filter(|child| child.resolution().is_none()) {
+                self.announced = child.threats();
+            }
+        }
+
+        Some(choice)
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        let player = game.current_player();
+        let staged_before = game
+            .history()
+            .iter()
+            .any(|(mover, mv)| Some(*mover) == player && matches!(mv, Move::Stage(_)));
+
+        if self.random_first_stage && !staged_before {
+            return game.pieces().choose(&mut self.rng).cloned();
+        }
+
+        self.inner.choose_stage(game)
+    }
+
+    fn analysis(&self) -> Option<&MoveAnalysis> {
+        self.inner.analysis()
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.inner.set_time_budget(budget);
+    }
+
+    fn game_over(&mut self, game: &Game, player: Player) {
+        self.inner.game_over(game, player);
+    }
+
+    fn standing(&self) -> Option<String> {
+        self.inner.standing()
+    }
+
+    fn take_announced_threats(&mut self) -> Vec<Threat> {
+        std::mem::take(&mut self.announced)
+    }
+
+    fn on_progress(&mut self, observer: ProgressObserver) {
+        self.inner.on_progress(observer);
+    }
+
+    fn set_cancel(&mut self, token: CancelToken) {
+        self.inner.set_cancel(token);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.inner.cancelled()
+    }
+}
+
+/// Levels of `AdaptiveAgent`, from playing at random half the time to
+/// searching in full
+const ADAPTIVE_LEVELS: usize = 10;
+
+/// Finished games an `AdaptiveAgent` judges its opponent on
+const ADAPTIVE_MEMORY: usize = 6;
+
+/// Plays a person at whatever strength keeps their results near even,
+/// stepping its level up after they win and down after they lose. Each
+/// level searches with more playouts and makes fewer deliberate mistakes
+/// than the one below. It explains each change of level as it makes it.
+pub struct AdaptiveAgent {
+    search: MonteCarloAgent,
+    level: usize,
+    /// The opponent's score in recent games, 1 for a win and 0.5 for a
+    /// draw, oldest first
+    results: Vec<f64>,
+    /// Pieces the opponent handed over that won on the spot when a safe
+    /// one was left, and pieces they staged in all, over recent games
+    blunders: Vec<(usize, usize)>,
+}
+
+impl AdaptiveAgent {
+    /// Start in the middle of the levels
+    pub fn new(seed: u64) -> Self {
+        let mut agent = AdaptiveAgent {
+            search: MonteCarloAgent {
+                playouts: 0,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                rng: StdRng::seed_from_u64(seed),
+            },
+            level: ADAPTIVE_LEVELS / 2 - 1,
+            results: Vec::new(),
+            blunders: Vec::new(),
+        };
+        agent.set_level(agent.level);
+
+        agent
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    fn set_level(&mut self, level: usize) {
+        self.level = level.min(ADAPTIVE_LEVELS - 1);
+        self.search.playouts = 10 + 40 * self.level;
+    }
+
+    /// Chance of making a random choice instead of searching
+    fn error_rate(&self) -> f64 {
+        (ADAPTIVE_LEVELS - 1 - self.level) as f64 * 0.05
+    }
+
+    fn errs(&mut self) -> bool {
+        self.search.rng.gen_bool(self.error_rate())
+    }
+
+    /// Count a finished game the agent played as `player`, and move the
+    /// level towards even results
+    pub fn record(&mut self, game: &Game, player: Player) {
+        let score = match game.resolution().map(|resolution| resolution.winner()) {
+            Some(Some(winner)) if winner == player => 0.0,
+            Some(Some(_)) => 1.0,
+            Some(None) => 0.5,
+            None => return,
+        };
+        self.results.push(score);
+        self.blunders.push(stage_blunders(game, player.toggle()));
+        if self.results.len() > ADAPTIVE_MEMORY {
+            self.results.remove(0);
+            self.blunders.remove(0);
+        }
+
+        let average = self.average();
+        let (blunders, stages) = self.blunder_totals();
+        let blundering = stages > 0 && blunders * 5 > stages;
+
+        // A win moves the level up, and so does a run of wins. A loss moves
+        // it down, further when the losses come from handing over wins.
+        if score > 0.5 || average > 0.6 {
+            self.set_level(self.level + 1);
+        } else if score < 0.5 || average < 0.4 {
+            let step = if blundering { 2 } else { 1 };
+            self.set_level(self.level.saturating_sub(step));
+        }
+    }
+
+    /// The opponent's average score over recent games, even before any
+    fn average(&self) -> f64 {
+        if self.results.is_empty() {
+            return 0.5;
+        }
+
+        self.results.iter().sum::<f64>() / self.results.len() as f64
+    }
+
+    fn blunder_totals(&self) -> (usize, usize) {
+        self.blunders
+            .iter()
+            .fold((0, 0), |(b, s), (blunders, stages)| {
+                (b + blunders, s + stages)
+            })
+    }
+
+    /// Where the agent stands and why
+    pub fn explain(&self) -> String {
+        let (blunders, stages) = self.blunder_totals();
+        let average = self.average();
+
+        format!(
+            "Adaptive engine at level {} of {}: {} playouts a move, {:.0}% of moves at random.\n\
+             You scored {:.0}% over the last {} games and handed over a winning piece {} times in {} turns.",
+            self.level + 1,
+            ADAPTIVE_LEVELS,
+            self.search.playouts,
+            self.error_rate() * 100.0,
+            average * 100.0,
+            self.results.len(),
+            blunders,
+            stages
+        )
+    }
+}
+
+/// Pieces `player` staged in `game` that let the opponent win on the spot
+/// although a safe piece was left, and how many pieces they staged in all
+fn stage_blunders(game: &Game, player: Player) -> (usize, usize) {
+    let mut game = game.clone();
+    let mut blunders = 0;
+    let mut stages = 0;
+
+    while let Some((mover, mv)) = game.undo() {
+        if let (true, Move::Stage(piece)) = (mover == player, mv) {
+            stages += 1;
+            let safe_left = game
+                .safe_pieces()
+                .iter()
+                .any(|safe| game.loses_immediately(safe).is_none());
+            if safe_left && game.loses_immediately(&piece).is_some() {
+                blunders += 1;
+            }
+        }
+    }
+
+    (blunders, stages)
+}
+
+impl Agent for AdaptiveAgent {
+    fn choose_placement(&mut self, game: &Game, piece: &Piece) -> Option<Coord> {
+        if self.errs() {
+            self.search.analysis = None;
+            return game.legal_squares().choose(&mut self.search.rng).copied();
+        }
+
+        self.search.choose_placement(game, piece)
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        if self.errs() {
+            self.search.analysis = None;
+            return game.pieces().choose(&mut self.search.rng).cloned();
+        }
+
+        self.search.choose_stage(game)
+    }
+
+    fn analysis(&self) -> Option<&MoveAnalysis> {
+        self.search.analysis()
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.search.set_time_budget(budget);
+    }
+
+    fn game_over(&mut self, game: &Game, player: Player) {
+        self.record(game, player);
+    }
+
+    fn standing(&self) -> Option<String> {
+        Some(self.explain())
+    }
+
+    fn set_cancel(&mut self, token: CancelToken) {
+        self.search.set_cancel(token);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.search.cancelled()
+    }
+}
+
+/// Plays any legal move
+pub struct RandomAgent {
+    pub rng: StdRng,
+}
+
+impl Agent for RandomAgent {
+    fn choose_placement(&mut self, game: &Game, _piece: &Piece) -> Option<Coord> {
+        game.legal_squares().choose(&mut self.rng).copied()
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        game.pieces().choose(&mut self.rng).cloned()
+    }
+}
+
+/// Scores each option by the outcome of random games played out from it
+pub struct MonteCarloAgent {
+    pub playouts: usize,
+    /// Time to spend on the next choice, searching with fewer playouts
+    /// than usual if that's all it allows
+    pub time_budget: Option<Duration>,
+    /// How the last choice was made
+    pub analysis: Option<MoveAnalysis>,
+    /// Stops the search when cancelled
+    pub cancel: Option<CancelToken>,
+    pub rng: StdRng,
+}
+
+impl MonteCarloAgent {
+    /// Make a choice with more and more playouts, up to the usual number,
+    /// while the next search, twice as long, is likely to fit in `budget`.
+    /// Endgames are solved exactly instead, taking no playouts, so they
+    /// aren't timed.
+    fn timed<T>(&mut self, budget: Duration, mut choose: impl FnMut(&mut Self) -> T) -> T {
+        let started = Instant::now();
+        let playouts = self.playouts;
+        self.playouts = playouts.min(25);
+        let mut choice = choose(self);
+
+        while self.playouts < playouts && started.elapsed() * 3 < budget && !self.cancelled() {
+            self.playouts = self.playouts.saturating_mul(2).min(playouts);
+            choice = choose(self);
+        }
+
+        self.playouts = playouts;
+        choice
+    }
+
+    /// Wins minus losses for `player` over random playouts from `game`,
+    /// per playout
+    fn score(&mut self, game: &Game, player: Player) -> f64 {
+        game.evaluate(player, self.playouts, &mut self.rng) * 2.0 - 1.0
+    }
+
+    fn best_placement(&mut self, game: &Game) -> Option<Coord> {
+        if let Some(analysis) = endgame::analyze(game) {
+            return placement(&mut self.analysis, analysis);
+        }
+
+        let game = &engine_view(game);
+        let player = game
+            .current_player()
+            .expect("agents are only asked to move in running games");
+
+        let cancel = self.cancel.clone().unwrap_or_default();
+        let candidates = shuffled(game.legal_squares(), &mut self.rng)
+            .into_iter()
+            .take_while(|_| !cancel.is_cancelled())
+            .map(|(row_idx, square_idx)| {
+                let child = game
+                    .child(Move::Place((row_idx, square_idx)))
+                    .expect("legal squares can take the staged piece");
+
+                let value = match child.resolution() {
+                    Some(Resolution::Win(..)) => 1.0,
+                    _ => self.score(&child, player),
+                };
+                ScoredMove {
+                    mv: Move::Place((row_idx, square_idx)),
+                    value,
+                }
+            })
+            .collect::<Vec<_>>();
+        if cancel.is_cancelled() {
+            return None;
+        }
+
+        placement(&mut self.analysis, MoveAnalysis::new(game, candidates))
+    }
+
+    fn best_stage(&mut self, game: &Game) -> Option<Piece> {
+        if let Some(analysis) = endgame::analyze(game) {
+            return stage(&mut self.analysis, analysis);
+        }
+
+        let game = &engine_view(game);
+        let player = game
+            .current_player()
+            .expect("agents are only asked to move in running games");
+
+        let cancel = self.cancel.clone().unwrap_or_default();
+        let candidates = shuffled(game.safe_pieces(), &mut self.rng)
+            .into_iter()
+            .take_while(|_| !cancel.is_cancelled())
+            .map(|piece| {
+                let child = game
+                    .child(Move::Stage(piece.clone()))
+                    .expect("remaining pieces can be staged");
+                ScoredMove {
+                    value: self.score(&child, player),
+                    mv: Move::Stage(piece.clone()),
+                }
+            })
+            .collect::<Vec<_>>();
+        if cancel.is_cancelled() {
+            return None;
+        }
+
+        stage(&mut self.analysis, MoveAnalysis::new(game, candidates))
+    }
+}
+
+impl Agent for MonteCarloAgent {
+    fn choose_placement(&mut self, game: &Game, _piece: &Piece) -> Option<Coord> {
+        match self.time_budget.take() {
+            Some(budget) if game.empty_squares().len() > endgame::ENDGAME_SQUARES => {
+                self.timed(budget, |agent| agent.best_placement(game))
+            }
+            _ => self.best_placement(game),
+        }
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        match self.time_budget.take() {
+            Some(budget) if game.empty_squares().len() > endgame::ENDGAME_SQUARES => {
+                self.timed(budget, |agent| agent.best_stage(game))
+            }
+            _ => self.best_stage(game),
+        }
+    }
+
+    fn analysis(&self) -> Option<&MoveAnalysis> {
+        self.analysis.as_ref()
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.time_budget = Some(budget);
+    }
+
+    fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+}
+
+/// Play random moves until the game ends, returning the winner if any
+pub fn random_playout(mut game: Game, rng: &mut impl Rng) -> Option<Player> {
+    // Quartos end the game, as in `engine_view`
+    game.set_rules(Rules {
+        call_quarto: false,
+        ..game.rules()
+    });
+
+    loop {
+        match game.phase() {
+            Phase::GameOver(resolution) => return resolution.winner(),
+            Phase::AwaitingPlacement(..) => {
+                let (row_idx, square_idx) = *game
+                    .legal_squares()
+                    .choose(rng)
+                    .expect("a staged piece always has a free square");
+                game.place(row_idx, square_idx)
+                    .expect("legal squares can take the staged piece");
+            }
+            Phase::AwaitingStage(_) => {
+                let piece = game
+                    .pieces()
+                    .choose(rng)
+                    .expect("staging only happens with pieces left")
+                    .clone();
+                game.stage(&piece).expect("remaining pieces can be staged");
+            }
+        }
+    }
+}
+
+/// The game as engines search it. They call every quarto the moment it's
+/// made, so they can treat quartos as ending the game the way they do
+/// without `Rules::call_quarto`.
+fn engine_view(game: &Game) -> Game {
+    let mut game = game.clone();
+    game.set_rules(Rules {
+        call_quarto: false,
+        ..game.rules()
+    });
+
+    game
+}
+
+/// Search a fixed number of turns ahead, skipping lines of play that can't
+/// change the choice. Options that can't beat the best one are searched
+/// only far enough to show that, so in `analysis` their values are upper
+/// bounds.
+pub struct MinimaxAgent {
+    pub depth: usize,
+    /// Time to spend on the next choice, searching less deeply than usual
+    /// if that's all it allows
+    pub time_budget: Option<Duration>,
+    /// How the last choice was made
+    pub analysis: Option<MoveAnalysis>,
+    /// Stops the search when cancelled
+    pub cancel: Option<CancelToken>,
+    /// Positions searched for the last choice
+    pub nodes: u64,
+    pub on_progress: Option<ProgressObserver>,
+    pub rng: StdRng,
+}
+
+const WIN_SCORE: i32 = 100;
+
+/// A fork past the search horizon wins by force, so it counts for nearly
+/// as much as a win the search can see
+const FORK_SCORE: i32 = WIN_SCORE / 2;
+
+impl MinimaxAgent {
+    /// Make a choice searching deeper and deeper, up to the usual depth,
+    /// telling `on_progress` after each depth. Given a `budget`, stop once
+    /// the next search, each ply costing many times the last, is unlikely
+    /// to fit in it.
+    fn deepen<T>(&mut self, budget: Option<Duration>, mut choose: impl FnMut(&mut Self) -> T) -> T {
+        let started = Instant::now();
+        let depth = self.depth;
+        self.depth = depth.min(1);
+        let mut nodes = 0;
+        let mut last_took = None;
+
+        loop {
+            let searching = Instant::now();
+            let choice = choose(self);
+            let took = searching.elapsed();
+            nodes += self.nodes;
+            if self.cancelled() {
+                self.depth = depth;
+                return choice;
+            }
+
+            let deeper =
+                self.depth < depth && budget.is_none_or(|budget| started.elapsed() * 10 < budget);
+            if let Some(observer) = &mut self.on_progress {
+                // The next depth should grow on this one as this one did on
+                // the last
+                let eta = if deeper {
+                    last_took
+                        .filter(|last: &Duration| last.as_nanos() > 0)
+                        .map(|last| took.mul_f64(took.as_secs_f64() / last.as_secs_f64()))
+                } else {
+                    Some(Duration::from_secs(0))
+                };
+                observer(&SearchProgress {
+                    depth: self.depth,
+                    nodes,
+                    best_so_far: self
+                        .analysis
+                        .as_ref()
+                        .and_then(|analysis| analysis.best().cloned()),
+                    eta,
+                });
+            }
+
+            if !deeper {
+                self.depth = depth;
+                self.nodes = nodes;
+                return choice;
+            }
+            last_took = Some(took);
+            self.depth += 1;
+        }
+    }
+
+    fn best_placement(&mut self, game: &Game) -> Option<Coord> {
+        if let Some(analysis) = endgame::analyze(game) {
+            self.nodes = 0;
+            return placement(&mut self.analysis, analysis);
+        }
+
+        let game = &engine_view(game);
+        let depth = self.depth;
+        let squares = shuffled(game.legal_squares(), &mut self.rng);
+        let table = Table::new(self.cancel.clone().unwrap_or_default());
+        let values = root_values(
+            &table,
+            &squares,
+            |search, (row_idx, square_idx), alpha, beta| {
+                let child = game
+                    .child(Move::Place((*row_idx, *square_idx)))
+                    .expect("legal squares can take the staged piece");
+
+                match child.resolution() {
+                    Some(Resolution::Win(..)) => WIN_SCORE + depth as i32,
+                    _ => search.stage_value(&child, depth.saturating_sub(1), alpha, beta),
+                }
+            },
+        );
+        let candidates = squares
+            .into_iter()
+            .zip(values)
+            .map(|(square, value)| ScoredMove {
+                mv: Move::Place(square),
+                value: minimax_value(value),
+            })
+            .collect();
+
+        self.nodes = table.nodes.load(Ordering::Relaxed);
+        debug!(
+            target: logging::ENGINE,
+            "searched {} positions to depth {}",
+            self.nodes,
+            depth
+        );
+        if table.cancel.is_cancelled() {
+            return None;
+        }
+        placement(&mut self.analysis, MoveAnalysis::new(game, candidates))
+    }
+
+    fn best_stage(&mut self, game: &Game) -> Option<Piece> {
+        if let Some(analysis) = endgame::analyze(game) {
+            self.nodes = 0;
+            return stage(&mut self.analysis, analysis);
+        }
+
+        let game = &engine_view(game);
+        let depth = self.depth;
+        let pieces = shuffled(game.safe_pieces(), &mut self.rng);
+        let table = Table::new(self.cancel.clone().unwrap_or_default());
+        let values = root_values(&table, &pieces, |search, piece, alpha, beta| {
+            let child = game
+                .child(Move::Stage((*piece).clone()))
+                .expect("remaining pieces can be staged");
+
+            -search.place_value(&child, depth, -beta, -alpha)
+        });
+        let candidates = pieces
+            .into_iter()
+            .zip(values)
+            .map(|(piece, value)| ScoredMove {
+                mv: Move::Stage(piece.clone()),
+                value: minimax_value(value),
+            })
+            .collect();
+
+        self.nodes = table.nodes.load(Ordering::Relaxed);
+        debug!(
+            target: logging::ENGINE,
+            "searched {} positions to depth {}",
+            self.nodes,
+            depth
+        );
+        if table.cancel.is_cancelled() {
+            return None;
+        }
+        stage(&mut self.analysis, MoveAnalysis::new(game, candidates))
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn choose_placement(&mut self, game: &Game, _piece: &Piece) -> Option<Coord> {
+        let budget = self.time_budget.take();
+        if budget.is_none() && self.on_progress.is_none() {
+            return self.best_placement(game);
+        }
+
+        self.deepen(budget, |agent| agent.best_placement(game))
+    }
+
+    fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+        let budget = self.time_budget.take();
+        if budget.is_none() && self.on_progress.is_none() {
+            return self.best_stage(game);
+        }
+
+        self.deepen(budget, |agent| agent.best_stage(game))
+    }
+
+    fn analysis(&self) -> Option<&MoveAnalysis> {
+        self.analysis.as_ref()
+    }
+
+    fn set_time_budget(&mut self, budget: Duration) {
+        self.time_budget = Some(budget);
+    }
+
+    fn on_progress(&mut self, observer: ProgressObserver) {
+        self.on_progress = Some(observer);
+    }
+
+    fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+}
+
+/// A minimax score on the scale of `ScoredMove::value`, where anything
+/// from a win on up counts as certain
+fn minimax_value(score: i32) -> f64 {
+    (f64::from(score) / f64::from(WIN_SCORE)).clamp(-1.0, 1.0)
+}
+
+/// Beyond any score a search can return
+const INFINITY: i32 = i32::MAX;
+
+/// Placements searched past the nominal depth along any one line of play,
+/// where the player placing is about to run out of safe pieces to stage
+const QUIESCENCE_PLIES: usize = 2;
+
+/// Locks the transposition table is split between, so that threads
+/// searching at once seldom wait on each other
+const TABLE_SHARDS: usize = 16;
+
+/// Depth from which positions searched are kept in the table. Closer to
+/// the horizon they're quicker to search again than to hash.
+const TABLE_DEPTH: usize = 2;
+
+/// How a kept value relates to the position's real value, given the window
+/// it was searched with
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    Exact,
+    /// The search was cut off above the window, so the real value is at
+    /// least this
+    Lower,
+    /// Nothing reached the window, so the real value is at most this
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    depth: usize,
+    value: i32,
+    bound: Bound,
+}
+
+/// Values of positions already searched, shared between the threads
+/// searching one choice. Positions are kept by `Game::canonical_hash`, so
+/// one entry serves every position symmetric to it.
+struct Table {
+    shards: Vec<Mutex<HashMap<u64, Entry>>>,
+    /// Positions searched by every thread together
+    nodes: AtomicU64,
+    /// Cuts every thread's search short once cancelled
+    cancel: CancelToken,
+}
+
+impl Table {
+    fn new(cancel: CancelToken) -> Self {
+        Table {
+            shards: (0..TABLE_SHARDS).map(|_| Mutex::default()).collect(),
+            nodes: AtomicU64::new(0),
+            cancel,
+        }
+    }
+
+    fn shard(&self, key: u64) -> &Mutex<HashMap<u64, Entry>> {
+        &self.shards[key as usize % TABLE_SHARDS]
+    }
+
+    fn get(&self, key: u64) -> Option<Entry> {
+        self.shard(key)
+            .lock()
+            .expect("no thread panics holding the table")
+            .get(&key)
+            .copied()
+    }
+
+    fn insert(&self, key: u64, entry: Entry) {
+        self.shard(key)
+            .lock()
+            .expect("no thread panics holding the table")
+            .insert(key, entry);
+    }
+}
+
+/// Values for each of the options at the root of a search. The first is
+/// searched on its own, to give a value for the rest to beat, then the
+/// rest are searched in parallel, sharing `table`, so the value of an
+/// option that can't beat the first is only an upper bound.
+fn root_values<T: Sync>(
+    table: &Table,
+    options: &[T],
+    value: impl Fn(&mut Search, &T, i32, i32) -> i32 + Sync,
+) -> Vec<i32> {
+    let (first, rest) = match options.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let alpha = value(&mut Search::new(table), first, -INFINITY, INFINITY);
+
+    let rest: Vec<i32> = rest
+        .par_iter()
+        .map_init(
+            || Search::new(table),
+            |search, option| {
+                search.window(alpha, INFINITY, false, |search, alpha, beta| {
+                    value(search, option, alpha, beta)
+                })
+            },
+        )
+        .collect();
+
+    iter::once(alpha).chain(rest).collect()
+}
+
+/// An alpha-beta search with principal variation search: the first option
+/// at each turn is searched with the full window, and the rest only
+/// closely enough to show they're no better, unless one turns out to be.
+/// That pays off when the best option tends to come first, so options are
+/// tried in order of how often they've cut searches short before. Winning
+/// placements need no ordering, since a placement that wins ends the search
+/// of that turn before any other is tried.
+struct Search<'a> {
+    table: &'a Table,
+    /// The last square and the last piece, by depth, that showed an option
+    /// was too good for the opponent to allow
+    killer_squares: Vec<Option<Coord>>,
+    killer_pieces: Vec<Option<u8>>,
+    /// How much searching cutoffs by each square and each piece have saved
+    square_history: [u32; 16],
+    piece_history: [u32; 16],
+    /// Placements searched past the nominal depth on the line being
+    /// searched
+    extensions: usize,
+}
+
+impl<'a> Search<'a> {
+    fn new(table: &'a Table) -> Self {
+        Search {
+            table,
+            killer_squares: Vec::new(),
+            killer_pieces: Vec::new(),
+            square_history: [0; 16],
+            piece_history: [0; 16],
+            extensions: 0,
+        }
+    }
+
+    /// Search an option with `value`: in full if it's the first, or else
+    /// with a null window just above `alpha`, searching again in full only
+    /// if it beats `alpha` after all
+    fn window(
+        &mut self,
+        alpha: i32,
+        beta: i32,
+        first: bool,
+        mut value: impl FnMut(&mut Self, i32, i32) -> i32,
+    ) -> i32 {
+        if first {
+            return value(self, alpha, beta);
+        }
+
+        let bound = value(self, alpha, alpha + 1);
+        if bound > alpha && bound < beta {
+            value(self, alpha, beta)
+        } else {
+            bound
+        }
+    }
+
+    /// Value for the current player, who has just placed and must stage
+    fn stage_value(&mut self, game: &Game, depth: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.table.nodes.fetch_add(1, Ordering::Relaxed);
+        // What a cancelled search finds is thrown away, so any value will do
+        if self.table.cancel.is_cancelled() {
+            return 0;
+        }
+        if depth == 0 && game.fork().is_some() {
+            return FORK_SCORE;
+        }
+
+        let mut pieces = game.safe_pieces();
+        let killer = self.killer_pieces.get(depth).copied().flatten();
+        pieces.sort_by_key(|piece| {
+            (
+                Some(piece.index()) != killer,
+                std::cmp::Reverse(self.piece_history[usize::from(piece.index())]),
+            )
+        });
+
+        let mut best = None;
+        for (idx, piece) in pieces.into_iter().enumerate() {
+            let child = game
+                .child(Move::Stage(piece.clone()))
+                .expect("remaining pieces can be staged");
+
+            let value = self.window(alpha, beta, idx == 0, |search, alpha, beta| {
+                -search.place_value(&child, depth, -beta, -alpha)
+            });
+
+            best = best.max(Some(value));
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                record(&mut self.killer_pieces, depth, piece.index());
+                self.piece_history[usize::from(piece.index())] += (depth * depth + 1) as u32;
+                break;
+            }
+        }
+
+        best.unwrap_or(0)
+    }
+
+    /// Value for the current player, who must place the staged piece.
+    /// Quicker wins score higher.
+    fn place_value(&mut self, game: &Game, depth: usize, alpha: i32, beta: i32) -> i32 {
+        self.table.nodes.fetch_add(1, Ordering::Relaxed);
+        // What a cancelled search finds is thrown away, so any value will do
+        if self.table.cancel.is_cancelled() {
+            return 0;
+        }
+        let piece = match game.staged() {
+            Some(piece) => piece,
+            None => return 0,
+        };
+
+        if !game.winning_squares(piece).is_empty() {
+            return WIN_SCORE + depth as i32;
+        }
+
+        if depth == 0 {
+            return self.quiescence(game, alpha, beta);
+        }
+
+        let key = if depth >= TABLE_DEPTH {
+            Some(game.canonical_hash())
+        } else {
+            None
+        };
+        let (mut alpha, mut beta) = (alpha, beta);
+        if let Some(entry) = key.and_then(|key| self.table.get(key)) {
+            if entry.depth == depth {
+                match entry.bound {
+                    Bound::Exact => return entry.value,
+                    Bound::Lower => alpha = alpha.max(entry.value),
+                    Bound::Upper => beta = beta.min(entry.value),
+                }
+                if alpha >= beta {
+                    return entry.value;
+                }
+            }
+        }
+        let window = (alpha, beta);
+
+        let mut squares = game.legal_squares();
+        let killer = self.killer_squares.get(depth).copied().flatten();
+        squares.sort_by_key(|(row_idx, square_idx)| {
+            (
+                Some((*row_idx, *square_idx)) != killer,
+                std::cmp::Reverse(self.square_history[row_idx * 4 + square_idx]),
+            )
+        });
+
+        let mut best = None;
+        for (idx, (row_idx, square_idx)) in squares.into_iter().enumerate() {
+            let child = game
+                .child(Move::Place((row_idx, square_idx)))
+                .expect("legal squares can take the staged piece");
+
+            // Placing and staging are the same player's turn, so the window
+            // carries over unflipped
+            let value = self.window(alpha, beta, idx == 0, |search, alpha, beta| {
+                search.stage_value(&child, depth - 1, alpha, beta)
+            });
+
+            best = best.max(Some(value));
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                record(&mut self.killer_squares, depth, (row_idx, square_idx));
+                self.square_history[row_idx * 4 + square_idx] += (depth * depth + 1) as u32;
+                break;
+            }
+        }
+
+        let value = best.unwrap_or(0);
+        if let Some(key) = key {
+            let bound = if value <= window.0 {
+                Bound::Upper
+            } else if value >= window.1 {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table.insert(
+                key,
+                Entry {
+                    depth,
+                    value,
+                    bound,
+                },
+            );
+        }
+
+        value
+    }
+
+    /// Value at the search horizon for the current player, who must place.
+    /// When they'll have at most one piece left to stage that doesn't lose
+    /// on the spot, the loss the horizon would otherwise hide may be two
+    /// turns away, so the search goes on another placement.
+    fn quiescence(&mut self, game: &Game, alpha: i32, beta: i32) -> i32 {
+        // Counted against the last placement rather than the one about to
+        // be made, which under `Rules::no_adjacent_repeat` only changes
+        // whether the search goes on, not what it finds
+        let safe = game
+            .pieces()
+            .iter()
+            .filter(|piece| game.winning_squares(piece).is_empty())
+            .count();
+        if safe > 1 || self.extensions == QUIESCENCE_PLIES {
+            return 0;
+        }
+
+        self.extensions += 1;
+        let value = self.place_value(game, 1, alpha, beta);
+        self.extensions -= 1;
+
+        value
+    }
+}
+
+/// Remember `killer` as the move that last cut off a search at `depth`
+fn record<T>(killers: &mut Vec<Option<T>>, depth: usize, killer: T) {
+    if killers.len() <= depth {
+        killers.resize_with(depth + 1, || None);
+    }
+    killers[depth] = Some(killer);
+}
+
+/// Keep `analysis` as the reasoning behind a placement, returning the
+/// square it rates best
+fn placement(kept: &mut Option<MoveAnalysis>, analysis: MoveAnalysis) -> Option<Coord> {
+    log_choice(&analysis);
+    let choice = match analysis.best() {
+        Some(Move::Place(square)) => Some(*square),
+        _ => None,
+    };
+    *kept = Some(analysis);
+
+    choice
+}
+
+/// Keep `analysis` as the reasoning behind a stage, returning the piece it
+/// rates best
+fn stage(kept: &mut Option<MoveAnalysis>, analysis: MoveAnalysis) -> Option<Piece> {
+    log_choice(&analysis);
+    let choice = match analysis.best() {
+        Some(Move::Stage(piece)) => Some(piece.clone()),
+        _ => None,
+    };
+    *kept = Some(analysis);
+
+    choice
+}
+
+fn log_choice(analysis: &MoveAnalysis) {
+    if let Some(best) = analysis.candidates.first() {
+        debug!(
+            target: logging::ENGINE,
+            "chose {} of {} options, valued {:.2}",
+            best.mv,
+            analysis.candidates.len(),
+            best.value
+        );
+    }
+}
+
+/// Random order so that equally scored options don't always resolve the
+/// same way
+fn shuffled<T>(mut items: Vec<T>, rng: &mut impl Rng) -> Vec<T> {
+    items.shuffle(rng);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::parse_position;
+    use crate::unplayed_pieces;
+    use std::sync::mpsc;
+
+    /// Three tall solid pieces on the top row, with a tall piece staged for
+    /// the current player
+    fn winnable_game() -> Game {
+        let board = parse_position("014./..../..../....").unwrap();
+
+        let staged = Piece::from_index(5).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+
+        Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::B, staged),
+            Rules::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn announced_threats_are_taken_once_test() {
+        let game = winnable_game();
+        let mut agent = HandicappedAgent {
+            inner: Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(3),
+            }),
+            announce_threats: true,
+            random_first_stage: false,
+            rng: StdRng::seed_from_u64(4),
+            announced: Vec::new(),
+        };
+
+        let staged = game.staged().unwrap().clone();
+        let square = agent.choose_placement(&game, &staged).unwrap();
+        let child = game.child(Move::Place(square)).unwrap();
+        let expected = if child.resolution().is_none() {
+            child.threats()
+        } else {
+            Vec::new()
+        };
+        assert_eq!(agent.take_announced_threats(), expected);
+        assert_eq!(agent.take_announced_threats(), []);
+    }
+
+    #[test]
+    fn split_command_test() {
+        assert_eq!(split_command(" inspect  7\n"), ("inspect", "7"));
+        assert_eq!(split_command("inspect\n"), ("inspect", ""));
+        assert_eq!(split_command("inspectf").0, "inspectf");
+        assert_eq!(split_command("inspection").0, "inspection");
+    }
+
+    #[test]
+    fn agents_take_immediate_wins_test() {
+        let game = winnable_game();
+        let piece = game.staged().cloned().unwrap();
+        let mut agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(MonteCarloAgent {
+                playouts: 10,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(MinimaxAgent {
+                depth: 1,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                nodes: 0,
+                on_progress: None,
+                rng: StdRng::seed_from_u64(1),
+            }),
+        ];
+
+        for agent in &mut agents {
+            assert_eq!(agent.choose_placement(&game, &piece), Some((0, 3)));
+        }
+    }
+
+    #[test]
+    fn timed_agents_keep_their_settings_test() {
+        let game = winnable_game();
+        let piece = game.staged().cloned().unwrap();
+        let mut monte_carlo = MonteCarloAgent {
+            playouts: 100_000,
+            time_budget: None,
+            analysis: None,
+            cancel: None,
+            rng: StdRng::seed_from_u64(1),
+        };
+        let mut minimax = MinimaxAgent {
+            depth: 6,
+            time_budget: None,
+            analysis: None,
+            cancel: None,
+            nodes: 0,
+            on_progress: None,
+            rng: StdRng::seed_from_u64(1),
+        };
+
+        // A budget this small leaves time for only the shallowest search
+        monte_carlo.set_time_budget(Duration::from_millis(1));
+        minimax.set_time_budget(Duration::from_millis(1));
+        assert_eq!(monte_carlo.choose_placement(&game, &piece), Some((0, 3)));
+        assert_eq!(minimax.choose_placement(&game, &piece), Some((0, 3)));
+
+        assert_eq!(
+            (monte_carlo.playouts, monte_carlo.time_budget),
+            (100_000, None)
+        );
+        assert_eq!((minimax.depth, minimax.time_budget), (6, None));
+    }
+
+    #[test]
+    fn progress_test() {
+        let game = winnable_game();
+        let piece = game.staged().cloned().unwrap();
+        let mut agent = MinimaxAgent {
+            depth: 2,
+            time_budget: None,
+            analysis: None,
+            cancel: None,
+            nodes: 0,
+            on_progress: None,
+            rng: StdRng::seed_from_u64(1),
+        };
+        let (sender, receiver) = mpsc::channel();
+        agent.on_progress(Box::new(move |progress| {
+            sender.send(progress.clone()).unwrap();
+        }));
+
+        assert_eq!(agent.choose_placement(&game, &piece), Some((0, 3)));
+        let reports: Vec<SearchProgress> = receiver.try_iter().collect();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| report.depth)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert!(!reports[0].is_done() && reports[1].is_done());
+        assert!(reports[0].nodes > 0 && reports[1].nodes > reports[0].nodes);
+        assert_eq!(agent.nodes, reports[1].nodes);
+        assert_eq!(reports[1].best_so_far, Some(Move::Place((0, 3))));
+        assert!(reports[1].to_string().starts_with("Depth 2, "));
+    }
+
+    #[test]
+    fn cancelled_search_changes_nothing_test() {
+        let game = winnable_game();
+        let piece = game.staged().cloned().unwrap();
+        let token = CancelToken::new();
+        token.cancel();
+
+        let mut monte_carlo = MonteCarloAgent {
+            playouts: 10,
+            time_budget: None,
+            analysis: None,
+            cancel: Some(token.clone()),
+            rng: StdRng::seed_from_u64(1),
+        };
+        assert_eq!(monte_carlo.choose_placement(&game, &piece), None);
+        assert!(monte_carlo.cancelled() && monte_carlo.analysis.is_none());
+
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(MinimaxAgent {
+                depth: 2,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                nodes: 0,
+                on_progress: None,
+                rng: StdRng::seed_from_u64(1),
+            }),
+        ];
+        agents[1].set_cancel(token);
+        let mut played = game.clone();
+        played.tick(&mut agents).unwrap();
+        assert_eq!(played.phase(), game.phase());
+        assert!(played.history().is_empty());
+        assert!(agents[1].analysis().is_none());
+
+        // Given a fresh token, it searches again
+        agents[1].set_cancel(CancelToken::new());
+        played.tick(&mut agents).unwrap();
+        assert_eq!(
+            played
+                .resolution()
+                .and_then(|resolution| resolution.winner()),
+            Some(Player::B)
+        );
+    }
+
+    #[test]
+    fn adaptive_agent_follows_results_test() {
+        // Player A hands over 5 with safe pieces left, and Player B wins
+        let board = parse_position("014./..../..../....").unwrap();
+        let pieces = unplayed_pieces(&board);
+        let mut lost = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingStage(Player::A),
+            Rules::default(),
+        )
+        .unwrap();
+        lost.stage(&Piece::from_index(5).unwrap()).unwrap();
+        lost.place(0, 3).unwrap();
+        assert_eq!(stage_blunders(&lost, Player::A), (1, 1));
+        assert_eq!(stage_blunders(&lost, Player::B), (0, 0));
+
+        let mut agent = AdaptiveAgent::new(1);
+        let start = agent.level();
+        agent.record(&lost, Player::A);
+        assert_eq!(agent.level(), start + 1);
+        agent.record(&lost, Player::B);
+        assert_eq!(agent.level(), start - 1);
+        assert!(agent.explain().contains("winning piece 1 times in 1 turns"));
+
+        for _ in 0..ADAPTIVE_LEVELS * 2 {
+            agent.record(&lost, Player::A);
+        }
+        assert_eq!(agent.level(), ADAPTIVE_LEVELS - 1);
+        assert_eq!(agent.error_rate(), 0.0);
+    }
+
+    #[test]
+    fn agents_stage_safe_pieces_test() {
+        let mut game = winnable_game();
+        game.place(1, 1).unwrap();
+        let mut agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(MonteCarloAgent {
+                playouts: 10,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(MinimaxAgent {
+                depth: 1,
+                time_budget: None,
+                analysis: None,
+                cancel: None,
+                nodes: 0,
+                on_progress: None,
+                rng: StdRng::seed_from_u64(1),
+            }),
+        ];
+
+        for agent in &mut agents {
+            let piece = agent.choose_stage(&game).unwrap();
+            assert!(game.winning_squares(&piece).is_empty());
+        }
+    }
+
+    /// Plain minimax with no pruning, to check the search against
+    fn full_place_value(game: &Game, depth: usize) -> i32 {
+        let piece = game.staged().unwrap();
+        if !game.winning_squares(piece).is_empty() {
+            return WIN_SCORE + depth as i32;
+        }
+        if depth == 0 {
+            return 0;
+        }
+
+        game.legal_squares()
+            .into_iter()
+            .map(|(row_idx, square_idx)| {
+                let mut child = game.clone();
+                child.place(row_idx, square_idx).unwrap();
+                if depth == 1 && child.fork().is_some() {
+                    return FORK_SCORE;
+                }
+                child
+                    .safe_pieces()
+                    .into_iter()
+                    .map(|piece| {
+                        let mut grandchild = child.clone();
+                        grandchild.stage(piece).unwrap();
+                        -full_place_value(&grandchild, depth - 1)
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn search_agrees_with_minimax_test() {
+        for seed in 0..4 {
+            let mut game = Game::new();
+            let mut agents: [Box<dyn Agent>; 2] = [
+                Box::new(RandomAgent {
+                    rng: StdRng::seed_from_u64(seed),
+                }),
+                Box::new(RandomAgent {
+                    rng: StdRng::seed_from_u64(seed + 100),
+                }),
+            ];
+            while game.resolution().is_none() && game.empty_squares().len() > 9 {
+                game.tick(&mut agents).unwrap();
+            }
+            if game.resolution().is_some() {
+                continue;
+            }
+            if game.staged().is_none() {
+                let piece = game.pieces()[0].clone();
+                game.stage(&piece).unwrap();
+            }
+
+            let game = engine_view(&game);
+            // With no extensions left, so it searches exactly as deep
+            let table = Table::new(CancelToken::new());
+            let mut search = Search {
+                extensions: QUIESCENCE_PLIES,
+                ..Search::new(&table)
+            };
+            for depth in 1..=3 {
+                assert_eq!(
+                    search.place_value(&game, depth, -INFINITY, INFINITY),
+                    full_place_value(&game, depth)
+                );
+            }
+
+            // Nor does splitting the root between threads change its value
+            if game.winning_squares(game.staged().unwrap()).is_empty() {
+                let squares = game.legal_squares();
+                let values = root_values(
+                    &table,
+                    &squares,
+                    |search, (row_idx, square_idx), alpha, beta| {
+                        search.extensions = QUIESCENCE_PLIES;
+                        let mut child = game.clone();
+                        child.place(*row_idx, *square_idx).unwrap();
+                        search.stage_value(&child, 2, alpha, beta)
+                    },
+                );
+                assert_eq!(values.into_iter().max(), Some(full_place_value(&game, 3)));
+            }
+        }
+    }
+
+    #[test]
+    fn quiescence_sees_past_horizon_test() {
+        // Wherever 8 goes, every piece left to stage wins for the opponent
+        let board = parse_position("5c.e/..a9/7.../3...").unwrap();
+        let staged = Piece::from_index(8).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::A, staged),
+            Rules::default(),
+        )
+        .unwrap();
+
+        let table = Table::new(CancelToken::new());
+        let mut horizon = Search {
+            extensions: QUIESCENCE_PLIES,
+            ..Search::new(&table)
+        };
+        assert_eq!(horizon.place_value(&game, 0, -INFINITY, INFINITY), 0);
+        assert_eq!(
+            Search::new(&Table::new(CancelToken::new())).place_value(&game, 0, -INFINITY, INFINITY),
+            -WIN_SCORE
+        );
+    }
+
+    #[test]
+    fn seeded_agents_repeat_choices_test() {
+        let mut game = Game::new();
+        game.stage(&game.pieces()[0].clone()).unwrap();
+
+        let choices: Vec<_> = (0..2)
+            .map(|_| {
+                let mut agent = engine_agent("montecarlo", 42).unwrap();
+                let piece = game.staged().cloned().unwrap();
+                let square = agent.choose_placement(&game, &piece).unwrap();
+
+                let mut game = game.clone();
+                game.place(square.0, square.1).unwrap();
+                (square, agent.choose_stage(&game))
+            })
+            .collect();
+
+        assert_eq!(choices[0], choices[1]);
+    }
+
+    #[test]
+    fn random_game_finishes_test() {
+        let mut game = Game::new();
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+        ];
+
+        for _ in 0..32 {
+            game.tick(&mut agents).unwrap();
+        }
+
+        assert!(game.resolution().is_some());
+    }
+}
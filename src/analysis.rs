@@ -0,0 +1,218 @@
+//! What an engine agent saw when it made a choice, kept so the choice can
+//! be explained to the person playing it.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::game::{square_name, Game, Move, Threat};
+use crate::{Coord, Piece};
+
+/// Candidates named in an explanation
+const SHOWN_CANDIDATES: usize = 3;
+
+/// One option an engine weighed
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoredMove {
+    pub mv: Move,
+    /// From -1 for a sure loss to 1 for a sure win, for the player moving
+    pub value: f64,
+}
+
+/// How an engine weighed its options for one choice
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveAnalysis {
+    /// Every option searched, best first
+    pub candidates: Vec<ScoredMove>,
+    /// Threats the best placement leaves on the board that weren't there
+    /// before it
+    pub threats_created: Vec<Threat>,
+    /// Threats the best placement fills with a piece that doesn't complete
+    /// them
+    pub threats_blocked: Vec<Threat>,
+    /// Pieces that would have let the opponent win straight away, each with
+    /// a square it wins on. The best piece to stage is only among them
+    /// when every piece is.
+    pub losing_pieces: Vec<(Piece, Coord)>,
+}
+
+impl MoveAnalysis {
+    /// Analyse the options searched from `game`, in any order
+    pub fn new(game: &Game, mut candidates: Vec<ScoredMove>) -> Self {
+        // Stable, so equal options keep the order they were searched in
+        candidates.sort_by(|a, b| b.value.partial_cmp(&a.value).unwrap_or(Ordering::Equal));
+        let mut analysis = MoveAnalysis {
+            candidates,
+            threats_created: Vec::new(),
+            threats_blocked: Vec::new(),
+            losing_pieces: Vec::new(),
+        };
+
+        match analysis.best().cloned() {
+            Some(Move::Place((row_idx, square_idx))) => {
+                let before = game.threats();
+                let child = game.child(Move::Place((row_idx, square_idx)));
+                // A winning placement ends the game rather than blocking
+                if let Some(child) = child.ok().filter(|child| child.resolution().is_none()) {
+                    let after = child.threats();
+                    analysis.threats_created = after
+                        .iter()
+                        .filter(|threat| !before.contains(threat))
+                        .copied()
+                        .collect();
+                    analysis.threats_blocked = before
+                        .iter()
+                        .filter(|threat| threat.open_square == (row_idx, square_idx))
+                        .copied()
+                        .collect();
+                }
+            }
+            Some(Move::Stage(_)) => {
+                analysis.losing_pieces = game
+                    .pieces()
+                    .iter()
+                    .filter_map(|piece| {
+                        game.loses_immediately(piece)
+                            .map(|square| (piece.clone(), square))
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+
+        analysis
+    }
+
+    /// The option the engine rates highest
+    pub fn best(&self) -> Option<&Move> {
+        self.candidates.first().map(|candidate| &candidate.mv)
+    }
+}
+
+impl fmt::Display for MoveAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let top: Vec<String> = self
+            .candidates
+            .iter()
+            .take(SHOWN_CANDIDATES)
+            .map(|candidate| {
+                format!(
+                    "{} ({:.0}% to win)",
+                    candidate.mv,
+                    (candidate.value + 1.0) * 50.0
+                )
+            })
+            .collect();
+        write!(f, "Considered {}", top.join(", "))?;
+
+        for threat in &self.threats_created {
+            write!(f, "\nSets up {}", threat)?;
+        }
+        for threat in &self.threats_blocked {
+            write!(f, "\nBlocks {}", threat)?;
+        }
+
+        if let Some(Move::Stage(piece)) = self.best() {
+            if self.losing_pieces.is_empty() {
+                write!(f, "\nNo piece left would have won on the spot")?;
+            } else if self.losing_pieces.iter().any(|(losing, _)| losing == piece) {
+                write!(
+                    f,
+                    "\nEvery piece left wins on the spot, so it gave {:x}",
+                    piece.index()
+                )?;
+            } else {
+                let losing: Vec<String> = self
+                    .losing_pieces
+                    .iter()
+                    .map(|(piece, (row_idx, square_idx))| {
+                        format!(
+                            "{:x} on {}",
+                            piece.index(),
+                            square_name(*row_idx, *square_idx)
+                        )
+                    })
+                    .collect();
+                write!(
+                    f,
+                    "\nGave {:x} rather than a piece that wins: {}",
+                    piece.index(),
+                    losing.join(", ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Phase, Rules};
+    use crate::position::parse_position;
+    use crate::{unplayed_pieces, Player};
+
+    fn scored(mv: Move, value: f64) -> ScoredMove {
+        ScoredMove { mv, value }
+    }
+
+    #[test]
+    fn explains_placement_test() {
+        let board = parse_position("01../..../..../....").unwrap();
+        let staged = Piece::from_index(4).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::A, staged),
+            Rules::default(),
+        )
+        .unwrap();
+
+        let analysis = MoveAnalysis::new(
+            &game,
+            vec![
+                scored(Move::Place((3, 3)), 0.0),
+                scored(Move::Place((0, 2)), 0.5),
+                scored(Move::Place((1, 1)), 0.0),
+                scored(Move::Place((2, 2)), -0.5),
+            ],
+        );
+
+        assert_eq!(analysis.best(), Some(&Move::Place((0, 2))));
+        assert_eq!(
+            analysis.to_string().lines().collect::<Vec<_>>(),
+            [
+                "Considered c1 (75% to win), d4 (50% to win), b2 (50% to win)",
+                "Sets up row 1 shares height, open at d1",
+                "Sets up row 1 shares density, open at d1",
+            ]
+        );
+    }
+
+    #[test]
+    fn explains_stage_test() {
+        let board = parse_position("014./..../..../....").unwrap();
+        let pieces = unplayed_pieces(&board);
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingStage(Player::A),
+            Rules::default(),
+        )
+        .unwrap();
+
+        let analysis = MoveAnalysis::new(
+            &game,
+            vec![scored(Move::Stage(Piece::from_index(0xf).unwrap()), 0.2)],
+        );
+
+        assert_eq!(analysis.losing_pieces.len(), 9);
+        assert!(analysis
+            .to_string()
+            .ends_with("Gave f rather than a piece that wins: 2 on d1, 3 on d1, 5 on d1, 6 on d1, 7 on d1, 8 on d1, 9 on d1, c on d1, d on d1"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Chess clocks for timed games. A time control like `3+2` gives each
+//! player three minutes, plus two seconds back for every piece they stage.
+//! A player whose time runs out loses, and engine agents are given a
+//! share of what they have left to think with.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::Player;
+
+/// Time controls offered by name, from quickest to slowest
+pub const PRESETS: &[&str] = &["1+0", "3+2", "5+0"];
+
+/// Remaining time under which a player is warned they're running low
+const LOW_TIME: Duration = Duration::from_secs(10);
+
+/// Turns an engine expects to have left to play, when dividing up its time
+const EXPECTED_TURNS: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    /// Time each player starts with
+    pub base: Duration,
+    /// Time given back after each piece staged
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    /// Read a control like `3+2`: minutes to start with, then seconds of
+    /// increment
+    pub fn parse(text: &str) -> Option<Self> {
+        let (minutes, seconds) = text.trim().split_once('+')?;
+
+        Some(TimeControl {
+            base: Duration::from_secs(minutes.parse::<u64>().ok()? * 60),
+            increment: Duration::from_secs(seconds.parse().ok()?),
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            self.base.as_secs() / 60,
+            self.increment.as_secs()
+        )
+    }
+}
+
+/// Both players' remaining time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock {
+            control,
+            remaining: [control.base; 2],
+        }
+    }
+
+    pub fn remaining(&self, player: Player) -> Duration {
+        self.remaining[player.index()]
+    }
+
+    /// Take `elapsed` off the player's time, adding the increment when
+    /// `staged` says they finished their turn by staging. Returns false,
+    /// leaving no time, if their flag fell.
+    pub fn charge(&mut self, player: Player, elapsed: Duration, staged: bool) -> bool {
+        let remaining = &mut self.remaining[player.index()];
+
+        match remaining.checked_sub(elapsed) {
+            Some(left) if left > Duration::from_secs(0) => {
+                *remaining = left;
+                if staged {
+                    *remaining += self.control.increment;
+                }
+                true
+            }
+            _ => {
+                *remaining = Duration::from_secs(0);
+                false
+            }
+        }
+    }
+
+    pub fn is_low(&self, player: Player) -> bool {
+        self.remaining(player) < LOW_TIME
+    }
+
+    /// How long an engine playing `player` should think about its next
+    /// choice: an even share of its time over the turns it expects to
+    /// have left, plus most of the increment it will get back. It shrinks
+    /// as the clock runs down.
+    pub fn budget(&self, player: Player) -> Duration {
+        let remaining = self.remaining(player);
+        let budget = remaining / EXPECTED_TURNS + self.control.increment * 3 / 4;
+
+        budget.min(remaining / 2)
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = |player: Player| {
+            let seconds = self.remaining(player).as_secs();
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        };
+
+        write!(
+            f,
+            "{} {}  {} {}",
+            Player::A,
+            time(Player::A),
+            Player::B,
+            time(Player::B)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        for preset in PRESETS {
+            let control = TimeControl::parse(preset).unwrap();
+            assert_eq!(control.to_string(), *preset);
+        }
+        assert_eq!(
+            TimeControl::parse("3+2"),
+            Some(TimeControl {
+                base: Duration::from_secs(180),
+                increment: Duration::from_secs(2),
+            })
+        );
+        assert_eq!(TimeControl::parse("3"), None);
+        assert_eq!(TimeControl::parse("a+2"), None);
+    }
+
+    #[test]
+    fn charge_test() {
+        let mut clock = Clock::new(TimeControl::parse("1+2").unwrap());
+
+        assert!(clock.charge(Player::A, Duration::from_secs(10), false));
+        assert!(clock.charge(Player::A, Duration::from_secs(10), true));
+        assert_eq!(clock.remaining(Player::A), Duration::from_secs(42));
+        assert_eq!(clock.to_string(), "Player A 0:42  Player B 1:00");
+        assert!(clock.budget(Player::A) < Duration::from_secs(21));
+
+        assert!(!clock.charge(Player::B, Duration::from_secs(60), true));
+        assert_eq!(clock.remaining(Player::B), Duration::from_secs(0));
+        assert!(clock.is_low(Player::B));
+        assert!(!clock.is_low(Player::A));
+    }
+}
@@ -0,0 +1,241 @@
+//! A sandbox for setting up positions by hand, to ask the engine about them
+//! or play on from them.
+//!
+//! Commands, one per line:
+//!
+//! - `put <piece> <square>`: put a piece like `5` on a square like `b3`,
+//!   returning whatever was there to the pool
+//! - `remove <square>`: return the piece on a square to the pool
+//! - `turn <a|b>`: set the player to act
+//! - `stage <piece|none>`: stage a piece for the player to act to place, or
+//!   leave them to stage one
+//! - `clear`: empty the board
+//! - `eval`: estimate who's winning
+//! - `play <a|b>`: play on from here as the given player
+//! - `quit`
+
+use crate::game::{parse_square, square_name, Game, Phase, Rules};
+use crate::position::{format_position, parse_position};
+use crate::{empty_board, unplayed_pieces, Board, Coord, Piece, Player};
+
+/// A position being set up, kept so that every piece is in one place
+pub struct Editor {
+    board: Board,
+    player: Player,
+    staged: Option<Piece>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Put(Piece, Coord),
+    Remove(Coord),
+    Turn(Player),
+    Stage(Option<Piece>),
+    Clear,
+    Evaluate,
+    Play(Player),
+    Quit,
+}
+
+/// Read one line of editor input
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["put", piece, square] => Ok(Command::Put(
+            parse_piece(piece)?,
+            parse_square(square).ok_or(format!("unknown square {}", square))?,
+        )),
+        ["remove", square] => Ok(Command::Remove(
+            parse_square(square).ok_or(format!("unknown square {}", square))?,
+        )),
+        ["turn", player] => Ok(Command::Turn(parse_player(player)?)),
+        ["stage", "none"] => Ok(Command::Stage(None)),
+        ["stage", piece] => Ok(Command::Stage(Some(parse_piece(piece)?))),
+        ["clear"] => Ok(Command::Clear),
+        ["eval"] => Ok(Command::Evaluate),
+        ["play", player] => Ok(Command::Play(parse_player(player)?)),
+        ["quit"] => Ok(Command::Quit),
+        _ => Err(format!("unknown command {}", line.trim())),
+    }
+}
+
+fn parse_piece(piece: &str) -> Result<Piece, String> {
+    Piece::parse(piece).ok_or(format!("unknown piece {}", piece))
+}
+
+fn parse_player(player: &str) -> Result<Player, String> {
+    match player {
+        "a" => Ok(Player::A),
+        "b" => Ok(Player::B),
+        _ => Err(format!("unknown player {}", player)),
+    }
+}
+
+impl Editor {
+    /// Start from an empty board with Player A to stage
+    pub fn new() -> Self {
+        Editor {
+            board: empty_board(),
+            player: Player::A,
+            staged: None,
+        }
+    }
+
+    /// Start from a position in the notation of the `position` module
+    pub fn from_position(position: &str) -> Result<Self, String> {
+        let board = parse_position(position).map_err(|err| err.to_string())?;
+
+        Ok(Editor {
+            board,
+            ..Editor::new()
+        })
+    }
+
+    pub fn put(&mut self, piece: Piece, (row_idx, square_idx): Coord) -> Result<(), String> {
+        if self.staged.as_ref() == Some(&piece) {
+            return Err(format!("{:x} is staged", piece.index()));
+        }
+        if let Some((row, square)) = self.find(&piece) {
+            return Err(format!(
+                "{:x} is already on {}",
+                piece.index(),
+                square_name(row, square)
+            ));
+        }
+
+        self.board[row_idx][square_idx] = Some(piece);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, (row_idx, square_idx): Coord) -> Result<(), String> {
+        match self.board[row_idx][square_idx].take() {
+            Some(_) => Ok(()),
+            None => Err(format!("{} is empty", square_name(row_idx, square_idx))),
+        }
+    }
+
+    pub fn set_turn(&mut self, player: Player) {
+        self.player = player;
+    }
+
+    pub fn stage(&mut self, piece: Option<Piece>) -> Result<(), String> {
+        if let Some(piece) = &piece {
+            if let Some((row, square)) = self.find(piece) {
+                return Err(format!(
+                    "{:x} is on {}",
+                    piece.index(),
+                    square_name(row, square)
+                ));
+            }
+        }
+
+        self.staged = piece;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.board = empty_board();
+    }
+
+    fn find(&self, piece: &Piece) -> Option<Coord> {
+        (0..4)
+            .flat_map(|row_idx| (0..4).map(move |square_idx| (row_idx, square_idx)))
+            .find(|&(row_idx, square_idx)| self.board[row_idx][square_idx].as_ref() == Some(piece))
+    }
+
+    /// The position as a game, with every piece not on the board or staged
+    /// left in the pool
+    pub fn game(&self, rules: Rules) -> Game {
+        let mut pieces = unplayed_pieces(&self.board);
+        pieces.retain(|piece| Some(piece) != self.staged.as_ref());
+        let phase = match &self.staged {
+            Some(piece) => Phase::AwaitingPlacement(self.player, piece.clone()),
+            None => Phase::AwaitingStage(self.player),
+        };
+
+        Game::from_position(self.board.clone(), pieces, phase, rules)
+            .expect("the editor keeps every piece in one place")
+    }
+
+    /// The position in the notation of the `position` module
+    pub fn position(&self) -> String {
+        format_position(&self.board)
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Editor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(index: u8) -> Piece {
+        Piece::from_index(index).unwrap()
+    }
+
+    #[test]
+    fn parse_command_test() {
+        assert_eq!(
+            parse_command("put a b3"),
+            Ok(Command::Put(piece(0xa), (2, 1)))
+        );
+        assert_eq!(parse_command(" stage none "), Ok(Command::Stage(None)));
+        assert_eq!(parse_command("play b"), Ok(Command::Play(Player::B)));
+        assert_eq!(
+            parse_command("put g b3"),
+            Err("unknown piece g".to_string())
+        );
+        assert_eq!(
+            parse_command("remove e5"),
+            Err("unknown square e5".to_string())
+        );
+        assert_eq!(parse_command("fly"), Err("unknown command fly".to_string()));
+    }
+
+    #[test]
+    fn edits_keep_pieces_in_one_place_test() {
+        let mut editor = Editor::new();
+        editor.put(piece(5), (2, 1)).unwrap();
+        editor.stage(Some(piece(3))).unwrap();
+
+        assert_eq!(
+            editor.put(piece(5), (0, 0)),
+            Err("5 is already on b3".to_string())
+        );
+        assert_eq!(editor.put(piece(3), (0, 0)), Err("3 is staged".to_string()));
+        assert_eq!(editor.stage(Some(piece(5))), Err("5 is on b3".to_string()));
+        assert_eq!(editor.remove((0, 0)), Err("a1 is empty".to_string()));
+
+        editor.set_turn(Player::B);
+        let game = editor.game(Rules::default());
+        assert_eq!(game.phase(), &Phase::AwaitingPlacement(Player::B, piece(3)));
+        assert_eq!(game.pieces().len(), 14);
+
+        editor.remove((2, 1)).unwrap();
+        editor.stage(None).unwrap();
+        assert_eq!(editor.game(Rules::default()).pieces().len(), 16);
+        assert_eq!(editor.position(), "..../..../..../....");
+    }
+
+    #[test]
+    fn finished_positions_test() {
+        let mut editor = Editor::from_position("014c/..../..../....").unwrap();
+        editor.set_turn(Player::B);
+
+        assert_eq!(
+            editor
+                .game(Rules::default())
+                .resolution()
+                .and_then(|r| r.winner()),
+            Some(Player::B)
+        );
+
+        editor.clear();
+        assert_eq!(editor.game(Rules::default()).resolution(), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! Exact solving of endgames. With only a few squares left the whole game
+//! tree is small enough to search to the end, so the engines switch from
+//! estimating to knowing once a game gets to `ENDGAME_SQUARES` empty
+//! squares. Positions reached more than once in a search, by placing the
+//! same pieces in a different order, are solved once.
+
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::analysis::{MoveAnalysis, ScoredMove};
+use crate::game::{Game, Move, Rules};
+use crate::logging;
+use crate::Player;
+
+/// Empty squares at or below which the engines solve a game exactly
+pub const ENDGAME_SQUARES: usize = 6;
+
+/// The result of perfect play, for the player to act
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Loss,
+    Draw,
+    Win,
+}
+
+impl Outcome {
+    /// The same result seen by the other player
+    fn flip(self) -> Self {
+        match self {
+            Outcome::Loss => Outcome::Win,
+            Outcome::Draw => Outcome::Draw,
+            Outcome::Win => Outcome::Loss,
+        }
+    }
+
+    /// On the scale of `ScoredMove::value`
+    fn value(self) -> f64 {
+        match self {
+            Outcome::Loss => -1.0,
+            Outcome::Draw => 0.0,
+            Outcome::Win => 1.0,
+        }
+    }
+}
+
+/// What decides the rest of a game: the board, the staged piece, and the
+/// last square placed on when `Rules::no_adjacent_repeat` depends on it.
+/// The pool is whatever isn't on the board or staged.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    squares: [u8; 16],
+    staged: Option<u8>,
+    last: Option<(usize, usize)>,
+}
+
+/// Nothing on a square, in `Key::squares`
+const EMPTY: u8 = 0xff;
+
+fn key(game: &Game) -> Key {
+    let mut squares = [EMPTY; 16];
+    for (row_idx, row) in game.board().iter().enumerate() {
+        for (square_idx, square) in row.iter().enumerate() {
+            if let Some(piece) = square {
+                squares[row_idx * 4 + square_idx] = piece.index();
+            }
+        }
+    }
+    let last = if game.rules().no_adjacent_repeat {
+        game.history().iter().rev().find_map(|(_, mv)| match mv {
+            Move::Place(square) => Some(*square),
+            Move::Stage(_) => None,
+        })
+    } else {
+        None
+    };
+
+    Key {
+        squares,
+        staged: game.staged().map(|piece| piece.index()),
+        last,
+    }
+}
+
+/// The game as it's solved, with quartos ending the game on the spot
+fn solving_view(game: &Game) -> Game {
+    let mut game = game.clone();
+    game.set_rules(Rules {
+        call_quarto: false,
+        ..game.rules()
+    });
+
+    game
+}
+
+/// The outcome of `mv` for `player`, who makes it
+fn after(game: &mut Game, mv: Move, player: Player, solved: &mut HashMap<Key, Outcome>) -> Outcome {
+    game.apply(mv).expect("generated moves are legal");
+    let outcome = match (game.resolution(), game.current_player()) {
+        (Some(resolution), _) => match resolution.winner() {
+            Some(winner) if winner == player => Outcome::Win,
+            Some(_) => Outcome::Loss,
+            None => Outcome::Draw,
+        },
+        (None, Some(next)) if next == player => solve_from(game, solved),
+        (None, _) => solve_from(game, solved).flip(),
+    };
+    game.undo();
+
+    outcome
+}
+
+fn solve_from(game: &mut Game, solved: &mut HashMap<Key, Outcome>) -> Outcome {
+    let key = key(game);
+    if let Some(outcome) = solved.get(&key) {
+        return *outcome;
+    }
+
+    let player = game
+        .current_player()
+        .expect("only games still going are solved");
+    let mut best = Outcome::Loss;
+    for mv in game.legal_moves() {
+        best = best.max(after(game, mv, player, solved));
+        if best == Outcome::Win {
+            break;
+        }
+    }
+
+    solved.insert(key, best);
+    best
+}
+
+/// The outcome of perfect play for the player to act, or `None` once the
+/// game is over. The time taken grows steeply with the empty squares.
+pub fn solve(game: &Game) -> Option<Outcome> {
+    game.current_player()?;
+
+    Some(solve_from(&mut solving_view(game), &mut HashMap::new()))
+}
+
+/// Every move for the player to act scored by its outcome under perfect
+/// play, if the game is going and no more than `ENDGAME_SQUARES` squares
+/// are empty
+pub fn analyze(game: &Game) -> Option<MoveAnalysis> {
+    let player = game.current_player()?;
+    if game.empty_squares().len() > ENDGAME_SQUARES {
+        return None;
+    }
+
+    let mut game = solving_view(game);
+    let mut solved = HashMap::new();
+    let candidates = game
+        .legal_moves()
+        .into_iter()
+        .map(|mv| ScoredMove {
+            value: after(&mut game, mv.clone(), player, &mut solved).value(),
+            mv,
+        })
+        .collect();
+    debug!(
+        target: logging::ENGINE,
+        "solved {} positions to the end",
+        solved.len()
+    );
+
+    Some(MoveAnalysis::new(&game, candidates))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{Agent, RandomAgent};
+    use crate::game::Phase;
+    use crate::position::parse_position;
+    use crate::{unplayed_pieces, Piece};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Plain minimax with no table, to check the table against
+    fn brute_force(game: &mut Game) -> Outcome {
+        let player = game.current_player().unwrap();
+
+        game.legal_moves()
+            .into_iter()
+            .map(|mv| {
+                game.apply(mv).unwrap();
+                let outcome = match (game.resolution(), game.current_player()) {
+                    (Some(resolution), _) => match resolution.winner() {
+                        Some(winner) if winner == player => Outcome::Win,
+                        Some(_) => Outcome::Loss,
+                        None => Outcome::Draw,
+                    },
+                    (None, Some(next)) if next == player => brute_force(game),
+                    (None, _) => brute_force(game).flip(),
+                };
+                game.undo();
+                outcome
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn immediate_win_test() {
+        let board = parse_position("014./..../..../....").unwrap();
+        let staged = Piece::from_index(5).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::B, staged),
+            Rules::default(),
+        )
+        .unwrap();
+
+        assert_eq!(solve(&game), Some(Outcome::Win));
+        // Too many empty squares for the engines to solve during play
+        assert_eq!(analyze(&game), None);
+    }
+
+    #[test]
+    fn table_agrees_with_brute_force_test() {
+        for seed in 0..6 {
+            let mut game = Game::new();
+            let mut agents: [Box<dyn Agent>; 2] = [
+                Box::new(RandomAgent {
+                    rng: StdRng::seed_from_u64(seed),
+                }),
+                Box::new(RandomAgent {
+                    rng: StdRng::seed_from_u64(seed + 100),
+                }),
+            ];
+            while game.resolution().is_none() && game.empty_squares().len() > 4 {
+                game.tick(&mut agents).unwrap();
+            }
+            if game.resolution().is_some() {
+                continue;
+            }
+
+            assert_eq!(solve(&game), Some(brute_force(&mut game.clone())));
+            let analysis = analyze(&game).unwrap();
+            assert_eq!(analysis.candidates[0].value, solve(&game).unwrap().value());
+        }
+    }
+}
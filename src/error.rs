@@ -0,0 +1,107 @@
+//! One error type for everything the crate can fail at, for callers that
+//! handle several kinds at once. Each module keeps its own error type, and
+//! converts into this one with `?`.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::game::{MoveError, SetupError};
+use crate::position::PositionError;
+use crate::rating::RatingError;
+use crate::record::RecordError;
+
+#[derive(Debug)]
+pub enum Error {
+    Move(MoveError),
+    Setup(SetupError),
+    Position(PositionError),
+    Rating(RatingError),
+    Record(RecordError),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Move(err) => write!(f, "{}", err),
+            Error::Setup(err) => write!(f, "{}", err),
+            Error::Position(err) => write!(f, "{}", err),
+            Error::Rating(err) => write!(f, "{}", err),
+            Error::Record(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Each variant shows its inner error's message as its own, so the chain
+/// carries on from the inner error's source rather than repeating it
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Move(err) => err.source(),
+            Error::Setup(err) => err.source(),
+            Error::Position(err) => err.source(),
+            Error::Rating(err) => err.source(),
+            Error::Record(err) => err.source(),
+            Error::Io(err) => err.source(),
+        }
+    }
+}
+
+impl From<MoveError> for Error {
+    fn from(err: MoveError) -> Self {
+        Error::Move(err)
+    }
+}
+
+impl From<SetupError> for Error {
+    fn from(err: SetupError) -> Self {
+        Error::Setup(err)
+    }
+}
+
+impl From<PositionError> for Error {
+    fn from(err: PositionError) -> Self {
+        Error::Position(err)
+    }
+}
+
+impl From<RatingError> for Error {
+    fn from(err: RatingError) -> Self {
+        Error::Rating(err)
+    }
+}
+
+impl From<RecordError> for Error {
+    fn from(err: RecordError) -> Self {
+        Error::Record(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::parse_position;
+    use crate::record::parse_records;
+    use std::error::Error as _;
+
+    #[test]
+    fn converts_and_chains_test() {
+        let read = |text: &str| -> Result<usize, Error> { Ok(parse_records(text)?.len()) };
+        let err = read("[Variant \"hexagonal\"]\n\n*").unwrap_err();
+        assert!(matches!(err, Error::Record(RecordError::UnknownVariant(_))));
+        assert_eq!(err.to_string(), "unknown variant hexagonal");
+
+        // Walking the chain doesn't repeat a message
+        let err = Error::from(parse_position("0000").unwrap_err());
+        assert_eq!(err.to_string(), "expected 4 rows, found 1");
+        assert!(err.source().is_none());
+    }
+}
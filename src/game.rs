@@ -0,0 +1,2243 @@
+//! A game of Quarto between two players.
+//!
+//! Each turn has two halves: the player to move places the piece their
+//! opponent staged for them, then stages one of the remaining pieces for
+//! the opponent. The first turn has nothing to place, so it starts with
+//! staging.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::agent::{random_playout, Agent};
+use crate::tray::Tray;
+use crate::{all_pieces, empty_board, Attribute, Board, Coord, Line, Piece, Player, Quarto};
+
+/// How a game ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    /// The player completed the quarto by placing its last piece
+    Win(Player, Quarto),
+    /// The player gave up, handing the win to their opponent
+    Resignation(Player),
+    /// The player's flag fell, handing the win to their opponent
+    Timeout(Player),
+    Draw,
+}
+
+impl Resolution {
+    pub fn winner(&self) -> Option<Player> {
+        match self {
+            Resolution::Win(player, _) => Some(*player),
+            Resolution::Resignation(player) | Resolution::Timeout(player) => Some(player.toggle()),
+            Resolution::Draw => None,
+        }
+    }
+}
+
+/// Running summary of the pieces on one line, so a completed line can be
+/// spotted without rescanning the board
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct LineState {
+    count: u8,
+    /// Index bits set in every piece on the line
+    all_set: u8,
+    /// Index bits clear in every piece on the line
+    all_clear: u8,
+}
+
+impl LineState {
+    fn add(&mut self, index: u8) {
+        if self.count == 0 {
+            self.all_set = index;
+            self.all_clear = !index & 0b1111;
+        } else {
+            self.all_set &= index;
+            self.all_clear &= !index;
+        }
+
+        self.count += 1;
+    }
+
+    /// Every attribute the pieces on the line have in common, in the
+    /// preference order of `shared_attribute`
+    fn shared_attributes(&self) -> impl Iterator<Item = Attribute> {
+        let shared = self.all_set | self.all_clear;
+
+        ATTRIBUTE_BITS
+            .iter()
+            .filter(move |(bit, _)| shared & bit != 0)
+            .map(|(_, attribute)| *attribute)
+    }
+
+    /// The attribute a full line has in common
+    fn shared_attribute(&self) -> Option<Attribute> {
+        if self.count < 4 {
+            return None;
+        }
+
+        self.shared_attributes().next()
+    }
+}
+
+/// The piece index bit behind each attribute
+const ATTRIBUTE_BITS: [(u8, Attribute); 4] = [
+    (0b1000, Attribute::Height),
+    (0b0100, Attribute::Color),
+    (0b0010, Attribute::Density),
+    (0b0001, Attribute::Shape),
+];
+
+/// A line one piece short of a quarto: three pieces sharing an attribute
+/// and an empty square
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threat {
+    pub line: Line,
+    pub attribute: Attribute,
+    pub open_square: Coord,
+}
+
+/// How many pieces with one value of an attribute, such as tall, are yet
+/// to be placed and how many are on the board
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueCount {
+    pub attribute: Attribute,
+    /// Index into `Attribute::values`
+    pub value: usize,
+    /// Counting the staged piece
+    pub unplayed: usize,
+    pub placed: usize,
+}
+
+/// What has to happen next in a game
+#[derive(Clone, Debug, PartialEq)]
+pub enum Phase {
+    /// The player must hand one of the remaining pieces to their opponent
+    AwaitingStage(Player),
+    /// The player must place the piece their opponent staged
+    AwaitingPlacement(Player, Piece),
+    GameOver(Resolution),
+}
+
+/// A move attempted in a phase that doesn't allow it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IllegalTransition {
+    /// Staging while a staged piece is still waiting to be placed
+    StageBeforePlacing,
+    /// Placing when nothing has been staged
+    PlaceWithoutStaging,
+    /// Any move once the game is decided
+    GameOver,
+}
+
+/// One half-turn
+#[derive(Clone, Debug, PartialEq)]
+pub enum Move {
+    /// Hand a piece to the opponent
+    Stage(Piece),
+    /// Put the staged piece on a square
+    Place(Coord),
+}
+
+/// Why a move was rejected
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoveError {
+    /// The move doesn't fit the current phase
+    Illegal(IllegalTransition),
+    /// The piece is on the board or already staged
+    Unavailable(Piece),
+    Occupied(Coord),
+    /// Next to the last piece placed, under `Rules::no_adjacent_repeat`
+    AdjacentToLast(Coord),
+    /// No take-backs left, or no move of the player's to take back
+    NoTakeBack,
+    /// Row or column index past the edge of the board
+    OffBoard(Coord),
+    /// A call with no quarto waiting to be called
+    NoQuarto,
+}
+
+/// Why a position can't be set up, with every piece needing to be in
+/// exactly one of the board, the staged slot or the pool
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetupError {
+    /// The piece is in more than one place
+    Duplicate(Piece),
+    /// The piece is nowhere at all
+    Missing(Piece),
+    /// The game was given as over in a way the board doesn't bear out
+    Resolution(Resolution),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetupError::Duplicate(piece) => {
+                write!(f, "{:x} appears more than once", piece.index())
+            }
+            SetupError::Missing(piece) => write!(
+                f,
+                "{:x} is not on the board, staged or left to stage",
+                piece.index()
+            ),
+            SetupError::Resolution(resolution) => {
+                write!(f, "the board doesn't fit \"{}\"", resolution)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+impl From<IllegalTransition> for MoveError {
+    fn from(err: IllegalTransition) -> Self {
+        MoveError::Illegal(err)
+    }
+}
+
+impl Phase {
+    /// The player who must act next, if the game is still going
+    pub fn player(&self) -> Option<Player> {
+        match self {
+            Phase::AwaitingStage(player) | Phase::AwaitingPlacement(player, _) => Some(*player),
+            Phase::GameOver(_) => None,
+        }
+    }
+
+    /// Staging hands the turn to the opponent, who must place `piece`
+    pub fn after_stage(&self, piece: &Piece) -> Result<Phase, IllegalTransition> {
+        match self {
+            Phase::AwaitingStage(player) => {
+                Ok(Phase::AwaitingPlacement(player.toggle(), piece.clone()))
+            }
+            Phase::AwaitingPlacement(..) => Err(IllegalTransition::StageBeforePlacing),
+            Phase::GameOver(_) => Err(IllegalTransition::GameOver),
+        }
+    }
+
+    /// Placing either decides the game or leaves the same player to stage.
+    /// `quarto` is the line the placement completed, if any.
+    pub fn after_place(
+        &self,
+        quarto: Option<Quarto>,
+        pieces_left: bool,
+    ) -> Result<Phase, IllegalTransition> {
+        match self {
+            Phase::AwaitingPlacement(player, _) => Ok(match quarto {
+                Some(quarto) => Phase::GameOver(Resolution::Win(*player, quarto)),
+                None if !pieces_left => Phase::GameOver(Resolution::Draw),
+                None => Phase::AwaitingStage(*player),
+            }),
+            Phase::AwaitingStage(_) => Err(IllegalTransition::PlaceWithoutStaging),
+            Phase::GameOver(_) => Err(IllegalTransition::GameOver),
+        }
+    }
+
+    /// The player to act concedes
+    pub fn after_resign(&self) -> Result<Phase, IllegalTransition> {
+        match self.player() {
+            Some(player) => Ok(Phase::GameOver(Resolution::Resignation(player))),
+            None => Err(IllegalTransition::GameOver),
+        }
+    }
+
+    /// The player to act runs out of time
+    pub fn after_timeout(&self) -> Result<Phase, IllegalTransition> {
+        match self.player() {
+            Some(player) => Ok(Phase::GameOver(Resolution::Timeout(player))),
+            None => Err(IllegalTransition::GameOver),
+        }
+    }
+}
+
+/// Optional rules on top of the standard game
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rules {
+    /// A piece may not go orthogonally next to the piece placed before it,
+    /// unless every empty square is next to it
+    pub no_adjacent_repeat: bool,
+    /// Turns each player may take back per game
+    pub take_backs: usize,
+    /// A quarto only wins once called, by its placer before staging or by
+    /// the opponent before placing, as in the tabletop game
+    pub call_quarto: bool,
+    /// The board wraps around its edges, so the broken diagonals of
+    /// `Line::wrapping` count as lines too
+    pub torus: bool,
+}
+
+/// A game in progress, or finished. Moves that the rules don't allow are
+/// rejected and leave the game unchanged.
+#[derive(Clone)]
+pub struct Game {
+    board: Board,
+    /// One entry per line, in `tracked_lines` order, whether or not the
+    /// rules count the wrapping ones
+    lines: [LineState; 16],
+    phase: Phase,
+    /// Pieces that are neither on the board nor staged
+    pieces: Vec<Piece>,
+    /// Moves made since the game was set up, with who made them
+    history: Vec<(Player, Move)>,
+    rules: Rules,
+    take_backs_used: [usize; 2],
+}
+
+impl Game {
+    /// An empty board with Player A to stage first
+    pub fn new() -> Self {
+        Game {
+            board: empty_board(),
+            lines: [LineState::default(); 16],
+            phase: Phase::AwaitingStage(Player::A),
+            pieces: all_pieces(),
+            history: Vec::new(),
+            rules: Rules::default(),
+            take_backs_used: [0; 2],
+        }
+    }
+
+    /// An empty board played under `rules`
+    pub fn with_rules(rules: Rules) -> Self {
+        Game {
+            rules,
+            ..Game::new()
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Play on under `rules`. The board isn't checked again, so a position
+    /// set up by hand should get its rules from `from_position`.
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    /// Set up a game part way through under `rules`, with `pieces` left to
+    /// stage. A quarto already on the board, counting the lines `rules`
+    /// count, ends the game as a win for whoever placed last: the player
+    /// `phase` says is to stage, or the one who staged for the player to
+    /// place. A `pieces` pool that's run out with nothing staged is a
+    /// draw.
+    ///
+    /// Every piece must be on the board, staged or in `pieces`, and only
+    /// in one of them. A `phase` that's already over must fit the board: a
+    /// win needs a quarto on it, a draw needs it full with none.
+    pub fn from_position(
+        board: Board,
+        mut pieces: Vec<Piece>,
+        phase: Phase,
+        rules: Rules,
+    ) -> Result<Self, SetupError> {
+        let staged = match &phase {
+            Phase::AwaitingPlacement(_, piece) => Some(piece),
+            _ => None,
+        };
+        let mut seen = [false; 16];
+        let placed = board.iter().flatten().flatten();
+        for piece in placed.chain(staged).chain(&pieces) {
+            let index = piece.index() as usize;
+            if seen[index] {
+                return Err(SetupError::Duplicate(piece.clone()));
+            }
+            seen[index] = true;
+        }
+        if let Some(missing) = all_pieces()
+            .into_iter()
+            .find(|piece| !seen[piece.index() as usize])
+        {
+            return Err(SetupError::Missing(missing));
+        }
+
+        pieces.sort_by_key(Piece::index);
+        let mut game = Game {
+            pieces,
+            ..Game::with_rules(rules)
+        };
+
+        let mut quarto = None;
+        for (row_idx, row) in board.iter().enumerate() {
+            for (square_idx, square) in row.iter().enumerate() {
+                if let Some(piece) = square {
+                    quarto = quarto.or(game.set_square(row_idx, square_idx, piece.clone()));
+                }
+            }
+        }
+
+        game.phase = match (quarto, phase) {
+            (Some(quarto), Phase::AwaitingStage(player)) => {
+                Phase::GameOver(Resolution::Win(player, quarto))
+            }
+            // The piece to place was staged by the player who placed last
+            (Some(quarto), Phase::AwaitingPlacement(player, _)) => {
+                Phase::GameOver(Resolution::Win(player.toggle(), quarto))
+            }
+            (None, Phase::AwaitingStage(_)) if game.pieces.is_empty() => {
+                Phase::GameOver(Resolution::Draw)
+            }
+            // A win needs a quarto to show for it, while any other ending
+            // would have been cut short by one
+            (quarto, Phase::GameOver(resolution)) => {
+                let fits = match resolution {
+                    Resolution::Win(..) => quarto.is_some(),
+                    Resolution::Draw => quarto.is_none() && game.pieces.is_empty(),
+                    Resolution::Resignation(_) | Resolution::Timeout(_) => quarto.is_none(),
+                };
+                if !fits {
+                    return Err(SetupError::Resolution(resolution));
+                }
+                Phase::GameOver(resolution)
+            }
+            (_, phase) => phase,
+        };
+
+        Ok(game)
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Pieces that are neither on the board nor staged, in index order
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+
+    /// Moves made since the game was set up, oldest first
+    pub fn history(&self) -> &[(Player, Move)] {
+        &self.history
+    }
+
+    /// The history as one line per staged piece, like "1. Player A gave 5,
+    /// Player B placed it on b3"
+    pub fn move_log(&self) -> Vec<String> {
+        let mut log: Vec<String> = Vec::new();
+
+        for (player, mv) in &self.history {
+            match mv {
+                Move::Stage(piece) => log.push(format!(
+                    "{}. {} gave {:x}",
+                    log.len() + 1,
+                    player,
+                    piece.index()
+                )),
+                Move::Place((row_idx, square_idx)) => {
+                    let placed = format!(
+                        "{} placed it on {}",
+                        player,
+                        square_name(*row_idx, *square_idx)
+                    );
+                    match log.last_mut() {
+                        Some(entry) => {
+                            entry.push_str(", ");
+                            entry.push_str(&placed);
+                        }
+                        // Set up with a piece already staged
+                        None => log.push(format!("1. {}", placed)),
+                    }
+                }
+            }
+        }
+
+        log
+    }
+
+    /// The move log of `branch`, a game played on from part way through
+    /// this one, from the first turn where the two part
+    pub fn variation(&self, branch: &Game) -> Vec<String> {
+        let original = self.move_log();
+
+        branch
+            .move_log()
+            .into_iter()
+            .enumerate()
+            .skip_while(|(idx, entry)| original.get(*idx) == Some(entry))
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    /// What has to happen next
+    pub fn phase(&self) -> &Phase {
+        &self.phase
+    }
+
+    /// The player who must act next, if the game is still going
+    pub fn current_player(&self) -> Option<Player> {
+        self.phase.player()
+    }
+
+    /// Piece the current player must place
+    pub fn staged(&self) -> Option<&Piece> {
+        match &self.phase {
+            Phase::AwaitingPlacement(_, piece) => Some(piece),
+            _ => None,
+        }
+    }
+
+    /// Squares still free, row by row
+    pub fn empty_squares(&self) -> Vec<Coord> {
+        let range = 0..self.board.len();
+
+        range
+            .clone()
+            .flat_map(|row_idx| range.clone().map(move |square_idx| (row_idx, square_idx)))
+            .filter(|(row_idx, square_idx)| self.board[*row_idx][*square_idx].is_none())
+            .collect()
+    }
+
+    /// Squares the staged piece may go on under the game's rules
+    pub fn legal_squares(&self) -> Vec<Coord> {
+        self.open_squares(self.last_placement())
+    }
+
+    /// Empty squares the next piece may go on if the last one placed went
+    /// on `last`
+    fn open_squares(&self, last: Option<Coord>) -> Vec<Coord> {
+        let empty = self.empty_squares();
+        let last = match last {
+            Some(last) if self.rules.no_adjacent_repeat => last,
+            _ => return empty,
+        };
+
+        let allowed: Vec<Coord> = empty
+            .iter()
+            .copied()
+            .filter(|square| !orthogonally_adjacent(*square, last))
+            .collect();
+
+        if allowed.is_empty() {
+            empty
+        } else {
+            allowed
+        }
+    }
+
+    /// Every move the player to act could make: a stage of each piece in
+    /// the pool, or a placement on each legal square. None once the game is
+    /// over.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        match self.phase {
+            Phase::AwaitingStage(_) => self.pieces.iter().cloned().map(Move::Stage).collect(),
+            Phase::AwaitingPlacement(..) => {
+                self.legal_squares().into_iter().map(Move::Place).collect()
+            }
+            Phase::GameOver(_) => Vec::new(),
+        }
+    }
+
+    fn last_placement(&self) -> Option<Coord> {
+        self.history.iter().rev().find_map(|(_, mv)| match mv {
+            Move::Place(square) => Some(*square),
+            Move::Stage(_) => None,
+        })
+    }
+
+    /// How the game ended, or `None` while it's still going
+    pub fn resolution(&self) -> Option<Resolution> {
+        match &self.phase {
+            Phase::GameOver(resolution) => Some(*resolution),
+            _ => None,
+        }
+    }
+
+    /// Pieces left and placed for each value of each attribute, both
+    /// values of an attribute in turn
+    pub fn attribute_census(&self) -> Vec<ValueCount> {
+        let placed: Vec<&Piece> = self.board.iter().flatten().flatten().collect();
+        let unplayed: Vec<&Piece> = self.pieces.iter().chain(self.staged()).collect();
+        let count = |pieces: &[&Piece], attribute, value| {
+            pieces
+                .iter()
+                .filter(|piece| piece.value(attribute) == value)
+                .count()
+        };
+
+        Attribute::all()
+            .iter()
+            .flat_map(|attribute| (0..2).map(move |value| (*attribute, value)))
+            .map(|(attribute, value)| ValueCount {
+                attribute,
+                value,
+                unplayed: count(&unplayed, attribute, value),
+                placed: count(&placed, attribute, value),
+            })
+            .collect()
+    }
+
+    /// Lines with three pieces sharing an attribute, one entry for each
+    /// shared attribute
+    pub fn threats(&self) -> Vec<Threat> {
+        let mut threats = Vec::new();
+
+        for (line, state) in tracked_lines().iter().zip(self.lines.iter()) {
+            if state.count != 3 || !self.counts(line) {
+                continue;
+            }
+
+            let open_square = *line
+                .squares()
+                .iter()
+                .find(|(row_idx, square_idx)| self.board[*row_idx][*square_idx].is_none())
+                .expect("a line with three pieces has one empty square");
+
+            for attribute in state.shared_attributes() {
+                threats.push(Threat {
+                    line: *line,
+                    attribute,
+                    open_square,
+                });
+            }
+        }
+
+        threats
+    }
+
+    /// The threats `piece` would turn into a quarto if placed on their
+    /// open square
+    pub fn completing_lines(&self, piece: &Piece) -> Vec<Threat> {
+        let lines = tracked_lines();
+
+        self.threats()
+            .into_iter()
+            .filter(|threat| {
+                let line_idx = lines
+                    .iter()
+                    .position(|line| *line == threat.line)
+                    .expect("threats are on lines of the board");
+                let mut state = self.lines[line_idx];
+                state.add(piece.index());
+                state
+                    .shared_attributes()
+                    .any(|attribute| attribute == threat.attribute)
+            })
+            .collect()
+    }
+
+    /// The chance that `player` wins, estimated from random playouts with
+    /// draws counting as half a win
+    pub fn evaluate(&self, player: Player, playouts: usize, rng: &mut impl Rng) -> f64 {
+        let points = |winner: Option<Player>| match winner {
+            Some(winner) if winner == player => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+
+        if let Some(resolution) = self.resolution() {
+            return points(resolution.winner());
+        }
+        if playouts == 0 {
+            return 0.5;
+        }
+
+        // Seed each playout up front so results don't depend on how rayon
+        // schedules them
+        let seeds: Vec<u64> = (0..playouts).map(|_| rng.gen()).collect();
+        let total: f64 = seeds
+            .into_par_iter()
+            .map(|seed| {
+                points(random_playout(
+                    self.clone(),
+                    &mut StdRng::seed_from_u64(seed),
+                ))
+            })
+            .sum();
+
+        total / playouts as f64
+    }
+
+    /// A square where the opponent could win straight away if `piece` were
+    /// staged for them
+    pub fn loses_immediately(&self, piece: &Piece) -> Option<Coord> {
+        self.winning_squares(piece).first().copied()
+    }
+
+    /// Squares where `piece` would complete a line if it were placed next:
+    /// the legal squares while it's staged, or the ones the opponent may
+    /// use after the last placement while it's being chosen. Wrapping lines
+    /// count on a torus.
+    pub fn winning_squares(&self, piece: &Piece) -> Vec<Coord> {
+        self.completing_squares(piece, self.legal_squares())
+    }
+
+    /// Those of `squares` where placing `piece` would complete a line
+    fn completing_squares(&self, piece: &Piece, squares: Vec<Coord>) -> Vec<Coord> {
+        let lines = tracked_lines();
+
+        squares
+            .into_iter()
+            .filter(|square| {
+                lines
+                    .iter()
+                    .zip(self.lines.iter())
+                    .filter(|(line, state)| {
+                        state.count == 3 && self.counts(line) && line.squares().contains(square)
+                    })
+                    .any(|(_, state)| {
+                        let mut state = *state;
+                        state.add(piece.index());
+                        state.shared_attribute().is_some()
+                    })
+            })
+            .collect()
+    }
+
+    /// Pieces that can be handed to the opponent without letting them win
+    /// on their next placement, as `crate::safe_pieces` but under this
+    /// game's rules
+    pub fn safe_pieces(&self) -> Vec<&Piece> {
+        let threats: Vec<(&Piece, usize)> = self
+            .pieces
+            .iter()
+            .map(|piece| (piece, self.winning_squares(piece).len()))
+            .collect();
+
+        let safe: Vec<&Piece> = threats
+            .iter()
+            .filter(|(_, count)| *count == 0)
+            .map(|(piece, _)| *piece)
+            .collect();
+
+        if !safe.is_empty() {
+            return safe;
+        }
+
+        threats
+            .iter()
+            .min_by_key(|(_, count)| *count)
+            .map(|(piece, _)| *piece)
+            .into_iter()
+            .collect()
+    }
+
+    /// A piece the player to stage can give to win by force: it completes
+    /// no quarto itself, and whichever square the opponent blocks with it,
+    /// every piece left completes a quarto somewhere else. This takes
+    /// threats open on at least two squares, hence a fork.
+    pub fn fork(&self) -> Option<Piece> {
+        if !matches!(self.phase, Phase::AwaitingStage(_)) || self.pieces.len() < 2 {
+            return None;
+        }
+
+        let wins: Vec<Vec<Coord>> = self
+            .pieces
+            .iter()
+            .map(|piece| self.winning_squares(piece))
+            .collect();
+        let mut open: Vec<Coord> = wins.iter().flatten().copied().collect();
+        open.sort_unstable();
+        open.dedup();
+        if open.len() < 2 {
+            return None;
+        }
+
+        // After the block, the next placement may be restricted by it
+        // rather than by the last placement now
+        let later_wins: Vec<Vec<Coord>> = self
+            .pieces
+            .iter()
+            .map(|piece| self.completing_squares(piece, self.empty_squares()))
+            .collect();
+        let staged_idx = (0..self.pieces.len()).find(|&staged_idx| {
+            wins[staged_idx].is_empty()
+                && open.iter().all(|blocked| {
+                    let after = self.open_squares(Some(*blocked));
+                    later_wins
+                        .iter()
+                        .enumerate()
+                        .filter(|(idx, _)| *idx != staged_idx)
+                        .all(|(_, squares)| {
+                            squares
+                                .iter()
+                                .any(|square| square != blocked && after.contains(square))
+                        })
+                })
+        })?;
+
+        Some(self.pieces[staged_idx].clone())
+    }
+
+    /// Numbers, as in `move_log`, of the moves whose placement left a fork
+    /// for the player who made it
+    pub fn fork_moves(&self) -> Vec<usize> {
+        let mut game = self.clone();
+        let mut moves = Vec::new();
+
+        loop {
+            if let Some((_, Move::Place(_))) = game.history.last() {
+                if game.fork().is_some() {
+                    let staged = game
+                        .history
+                        .iter()
+                        .filter(|(_, mv)| matches!(mv, Move::Stage(_)))
+                        .count();
+                    moves.push(staged.max(1));
+                }
+            }
+
+            if game.undo().is_none() {
+                break;
+            }
+        }
+
+        moves.reverse();
+        moves
+    }
+
+    /// Concede the game on behalf of the current player
+    pub fn resign(&mut self) {
+        if let Ok(phase) = self.phase.after_resign() {
+            self.phase = phase;
+        }
+    }
+
+    /// End the game with the current player losing on time
+    pub fn lose_on_time(&mut self) {
+        if let Ok(phase) = self.phase.after_timeout() {
+            self.phase = phase;
+        }
+    }
+
+    /// Hand `piece` to the opponent, who must place it next
+    pub fn stage(&mut self, piece: &Piece) -> Result<(), MoveError> {
+        let phase = self.phase.after_stage(piece)?;
+        let idx = self
+            .pieces
+            .iter()
+            .position(|p| p == piece)
+            .ok_or_else(|| MoveError::Unavailable(piece.clone()))?;
+
+        let player = self.phase.player().expect("staging needs a player to act");
+        self.pieces.remove(idx);
+        self.phase = phase;
+        self.history.push((player, Move::Stage(piece.clone())));
+
+        Ok(())
+    }
+
+    /// Place the staged piece on an empty square
+    pub fn place(&mut self, row_idx: usize, square_idx: usize) -> Result<(), MoveError> {
+        if let Phase::GameOver(_) = self.phase {
+            return Err(IllegalTransition::GameOver.into());
+        }
+        let square = self
+            .board
+            .get(row_idx)
+            .and_then(|row| row.get(square_idx))
+            .ok_or(MoveError::OffBoard((row_idx, square_idx)))?;
+        if square.is_some() {
+            return Err(MoveError::Occupied((row_idx, square_idx)));
+        }
+
+        let piece = self
+            .staged()
+            .cloned()
+            .ok_or(IllegalTransition::PlaceWithoutStaging)?;
+        if !self.legal_squares().contains(&(row_idx, square_idx)) {
+            return Err(MoveError::AdjacentToLast((row_idx, square_idx)));
+        }
+        let player = self.phase.player().expect("placing needs a player to act");
+        let quarto = self.set_square(row_idx, square_idx, piece);
+        // With the last piece placed there's no turn left to call it in
+        let quarto = quarto.filter(|_| !self.rules.call_quarto || self.pieces.is_empty());
+        self.phase = self.phase.after_place(quarto, !self.pieces.is_empty())?;
+        self.history
+            .push((player, Move::Place((row_idx, square_idx))));
+
+        Ok(())
+    }
+
+    /// The quarto completed by the last placement under
+    /// `Rules::call_quarto`, while it can still be called
+    pub fn uncalled_quarto(&self) -> Option<Quarto> {
+        if !self.rules.call_quarto || self.resolution().is_some() {
+            return None;
+        }
+
+        let square = self.last_placement()?;
+        tracked_lines()
+            .iter()
+            .zip(self.lines.iter())
+            .filter(|(line, _)| self.counts(line) && line.squares().contains(&square))
+            .find_map(|(line, state)| {
+                state.shared_attribute().map(|attribute| Quarto {
+                    line: *line,
+                    attribute,
+                })
+            })
+    }
+
+    /// Call the uncalled quarto, winning it for the current player
+    pub fn call_quarto(&mut self) -> Result<(), MoveError> {
+        let quarto = self.uncalled_quarto().ok_or(MoveError::NoQuarto)?;
+        let player = self
+            .phase
+            .player()
+            .expect("an uncalled quarto needs a game going");
+        self.phase = Phase::GameOver(Resolution::Win(player, quarto));
+
+        Ok(())
+    }
+
+    /// A hash of the position that's the same for every position it can
+    /// be turned into by rotating or reflecting the board, or by swapping
+    /// the two values of any attribute on every piece. Positions that hash
+    /// the same play the same, given the same rules. Only the board, the
+    /// staged piece and, for `Rules::no_adjacent_repeat`, the last square
+    /// placed on count, not which player is to act.
+    pub fn canonical_hash(&self) -> u64 {
+        let last = self.last_placement();
+        let phase = match self.phase {
+            Phase::AwaitingStage(_) => 0,
+            Phase::AwaitingPlacement(..) => 1,
+            Phase::GameOver(_) => 2,
+        };
+
+        // The smallest encoding over every symmetry stands for them all
+        let canonical = (0..8)
+            .flat_map(|symmetry| (0..16).map(move |flip| (symmetry, flip)))
+            .map(|(symmetry, flip)| {
+                let mut encoding = [EMPTY_SQUARE; 19];
+                for (row_idx, row) in self.board.iter().enumerate() {
+                    for (square_idx, square) in row.iter().enumerate() {
+                        if let Some(piece) = square {
+                            let (row, col) = transform((row_idx, square_idx), symmetry);
+                            encoding[row * 4 + col] = piece.index() ^ flip;
+                        }
+                    }
+                }
+                if let Some(piece) = self.staged() {
+                    encoding[16] = piece.index() ^ flip;
+                }
+                encoding[17] = phase;
+                if let Some(square) = last.filter(|_| self.rules.no_adjacent_repeat) {
+                    let (row, col) = transform(square, symmetry);
+                    encoding[18] = (row * 4 + col) as u8;
+                }
+
+                encoding
+            })
+            .min()
+            .expect("there are always symmetries to try");
+
+        let mut hasher = DefaultHasher::new();
+        canonical.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Count the move sequences `depth` half-turns long from here, each
+    /// stage and each placement being one half-turn. A game that ends
+    /// sooner has no sequences past its end, so counts check that both
+    /// move generation and win detection are right. Calls of quarto under
+    /// `Rules::call_quarto` aren't counted as moves.
+    pub fn perft(&self, depth: usize) -> u64 {
+        self.clone().count_moves(depth)
+    }
+
+    fn count_moves(&mut self, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        self.legal_moves()
+            .into_iter()
+            .map(|mv| {
+                self.apply(mv).expect("generated moves are legal");
+                let count = self.count_moves(depth - 1);
+                self.undo();
+                count
+            })
+            .sum()
+    }
+
+    /// Make a move of either kind
+    pub fn apply(&mut self, mv: Move) -> Result<(), MoveError> {
+        match mv {
+            Move::Stage(piece) => self.stage(&piece),
+            Move::Place((row_idx, square_idx)) => self.place(row_idx, square_idx),
+        }
+    }
+
+    /// The position after `mv`, leaving this game as it is, for a search
+    /// to look at without making and undoing the move. Only the board,
+    /// lines, phase and pool are copied. The history keeps just what the
+    /// rules look back at, the last placement, so the child can't be
+    /// undone past `mv` or logged from the start.
+    pub fn child(&self, mv: Move) -> Result<Game, MoveError> {
+        let last_placement = self
+            .history
+            .iter()
+            .rev()
+            .find(|(_, mv)| matches!(mv, Move::Place(_)))
+            .cloned();
+
+        let mut child = Game {
+            board: self.board.clone(),
+            lines: self.lines,
+            phase: self.phase.clone(),
+            pieces: self.pieces.clone(),
+            history: last_placement.into_iter().collect(),
+            rules: self.rules,
+            take_backs_used: self.take_backs_used,
+        };
+        child.apply(mv)?;
+
+        Ok(child)
+    }
+
+    /// Read a move in notation: a square like `b3` while a piece is
+    /// staged, otherwise a piece's hex digit
+    pub fn parse_move(&self, text: &str) -> Option<Move> {
+        match self.phase {
+            Phase::AwaitingPlacement(..) => parse_square(text).map(Move::Place),
+            Phase::AwaitingStage(_) => Piece::parse(text).map(Move::Stage),
+            Phase::GameOver(_) => None,
+        }
+    }
+
+    /// Undo the most recent move, returning it along with who made it
+    pub fn undo(&mut self) -> Option<(Player, Move)> {
+        let (player, mv) = self.history.pop()?;
+
+        match &mv {
+            Move::Stage(piece) => {
+                // Keep the pool in index order
+                let idx = self
+                    .pieces
+                    .iter()
+                    .position(|p| p.index() > piece.index())
+                    .unwrap_or(self.pieces.len());
+                self.pieces.insert(idx, piece.clone());
+                self.phase = Phase::AwaitingStage(player);
+            }
+            Move::Place((row_idx, square_idx)) => {
+                let piece = self.board[*row_idx][*square_idx]
+                    .take()
+                    .expect("a placed square holds its piece");
+                self.recount_lines();
+                self.phase = Phase::AwaitingPlacement(player, piece);
+            }
+        }
+
+        Some((player, mv))
+    }
+
+    /// Whether the current player has a turn to take back and a take-back
+    /// left to spend on it
+    pub fn can_take_back(&self) -> bool {
+        match self.phase.player() {
+            Some(player) => {
+                self.take_backs_used[player.index()] < self.rules.take_backs
+                    && self.history.iter().any(|(p, _)| *p == player)
+            }
+            None => false,
+        }
+    }
+
+    /// Undo the current player's last move and everything played since,
+    /// spending one of their take-backs
+    pub fn take_back(&mut self) -> Result<(), MoveError> {
+        let player = self.phase.player().ok_or(IllegalTransition::GameOver)?;
+        if !self.can_take_back() {
+            return Err(MoveError::NoTakeBack);
+        }
+
+        while let Some((mover, _)) = self.undo() {
+            if mover == player {
+                break;
+            }
+        }
+        self.take_backs_used[player.index()] += 1;
+
+        Ok(())
+    }
+
+    /// Whether `line` is a line under the rules of this game
+    fn counts(&self, line: &Line) -> bool {
+        self.rules.torus || !line.wraps()
+    }
+
+    /// Rebuild every line's summary from the board
+    fn recount_lines(&mut self) {
+        for (line, state) in tracked_lines().iter().zip(self.lines.iter_mut()) {
+            *state = LineState::default();
+            for (row_idx, square_idx) in line.squares().iter() {
+                if let Some(piece) = &self.board[*row_idx][*square_idx] {
+                    state.add(piece.index());
+                }
+            }
+        }
+    }
+
+    /// Put a piece on the board and update the lines running through it,
+    /// returning the first line it completes with a shared attribute
+    fn set_square(&mut self, row_idx: usize, square_idx: usize, piece: Piece) -> Option<Quarto> {
+        let index = piece.index();
+        self.board[row_idx][square_idx] = Some(piece);
+        let mut quarto = None;
+        let torus = self.rules.torus;
+
+        for (line, state) in tracked_lines().iter().zip(self.lines.iter_mut()) {
+            if !line.squares().contains(&(row_idx, square_idx)) {
+                continue;
+            }
+
+            state.add(index);
+            if quarto.is_none() && (torus || !line.wraps()) {
+                quarto = state.shared_attribute().map(|attribute| Quarto {
+                    line: *line,
+                    attribute,
+                });
+            }
+        }
+
+        quarto
+    }
+
+    /// Ask the current player's agent for their next half-turn and apply
+    /// it, failing if the agent picks a move that isn't allowed
+    pub fn tick(&mut self, agents: &mut [Box<dyn Agent>; 2]) -> Result<(), MoveError> {
+        let player = match self.phase.player() {
+            Some(player) => player,
+            None => return Ok(()),
+        };
+        let agent = &mut agents[player.index()];
+
+        if self.uncalled_quarto().is_some() && agent.calls_quarto() {
+            return self.call_quarto();
+        }
+
+        let choice = match self.phase.clone() {
+            Phase::AwaitingPlacement(_, piece) => agent
+                .choose_placement(self, &piece)
+                .map(|(row_idx, square_idx)| Move::Place((row_idx, square_idx))),
+            Phase::AwaitingStage(_) => agent.choose_stage(self).map(Move::Stage),
+            Phase::GameOver(_) => None,
+        };
+
+        match choice {
+            Some(mv) => self.apply(mv),
+            // Nothing was chosen, so nothing changes
+            None if agent.cancelled() => Ok(()),
+            None if agent.wants_take_back() => self.take_back(),
+            None if self.uncalled_quarto().is_some() && agent.calls_quarto() => self.call_quarto(),
+            None => {
+                self.resign();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::new()
+    }
+}
+
+/// No piece, in `Game::canonical_hash` encodings
+const EMPTY_SQUARE: u8 = 0xff;
+
+/// Where one of the board's eight symmetries takes a square: the first
+/// bit transposes, the other two flip the rows and the columns
+fn transform((row_idx, square_idx): Coord, symmetry: u8) -> Coord {
+    let (row_idx, square_idx) = if symmetry & 1 != 0 {
+        (square_idx, row_idx)
+    } else {
+        (row_idx, square_idx)
+    };
+    let row_idx = if symmetry & 2 != 0 {
+        3 - row_idx
+    } else {
+        row_idx
+    };
+    let square_idx = if symmetry & 4 != 0 {
+        3 - square_idx
+    } else {
+        square_idx
+    };
+
+    (row_idx, square_idx)
+}
+
+fn orthogonally_adjacent(a: Coord, b: Coord) -> bool {
+    let rows = (a.0 as isize - b.0 as isize).abs();
+    let cols = (a.1 as isize - b.1 as isize).abs();
+
+    rows + cols == 1
+}
+
+/// Every line a game keeps a summary of: those of `Line::all`, then those
+/// of `Line::wrapping`
+fn tracked_lines() -> [Line; 16] {
+    let mut lines = [Line::Diagonal; 16];
+    lines[..10].copy_from_slice(&Line::all());
+    lines[10..].copy_from_slice(&Line::wrapping());
+    lines
+}
+
+/// Name a square with a column letter and a row number, like `b3`
+pub fn square_name(row_idx: usize, square_idx: usize) -> String {
+    format!("{}{}", (b'a' + square_idx as u8) as char, row_idx + 1)
+}
+
+/// Parse a square name like `b3` into row and column indexes
+pub fn parse_square(name: &str) -> Option<Coord> {
+    let mut chars = name.trim().chars();
+    let square_idx = match chars.next()? {
+        c @ 'a'..='d' => c as usize - 'a' as usize,
+        _ => return None,
+    };
+    let row_idx = match chars.next()?.to_digit(10)? {
+        row @ 1..=4 => row as usize - 1,
+        _ => return None,
+    };
+
+    if chars.next().is_some() {
+        return None;
+    }
+
+    Some((row_idx, square_idx))
+}
+
+impl Game {
+    /// The game drawn as by `Display`, but with every placed piece face
+    /// down except the one placed last, for playing from memory
+    pub fn blindfolded(&self) -> Blindfolded<'_> {
+        Blindfolded(self)
+    }
+
+    /// The game drawn as by `Display`, or as by `blindfolded` if `blind`,
+    /// with the unplayed pieces laid out by `tray`
+    pub fn arranged<'a>(&'a self, tray: &'a Tray, blind: bool) -> Arranged<'a> {
+        Arranged {
+            game: self,
+            tray,
+            blind,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, blind: bool, tray: &Tray) -> fmt::Result {
+        let shown = if blind { self.last_placement() } else { None };
+        // Empty squares the staged piece can't go on are crossed out
+        let legal = match self.phase {
+            Phase::AwaitingPlacement(..) => self.legal_squares(),
+            _ => self.empty_squares(),
+        };
+
+        writeln!(f, "  a b c d")?;
+        for (row_idx, row) in self.board.iter().enumerate() {
+            write!(f, "{}", row_idx + 1)?;
+            for (square_idx, square) in row.iter().enumerate() {
+                match square {
+                    Some(_) if blind && shown != Some((row_idx, square_idx)) => write!(f, " ?")?,
+                    Some(piece) => write!(f, " {:x}", piece.index())?,
+                    None if legal.contains(&(row_idx, square_idx)) => write!(f, " .")?,
+                    None => write!(f, " x")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        if self.rules.torus {
+            writeln!(f, "  (lines wrap around the edges)")?;
+        }
+
+        // The tray keeps every piece in its own slot, leaving the staged
+        // piece's slot marked so it's clear where it came from
+        writeln!(f)?;
+        if let Some((attribute, value)) = tray.filter {
+            writeln!(f, "Only {} pieces:", attribute.values()[value])?;
+        }
+        let slots: Vec<Piece> = all_pieces()
+            .into_iter()
+            .filter(|piece| self.pieces.contains(piece) || self.staged() == Some(piece))
+            .collect();
+        for (heading, pieces) in tray.arrange(&slots) {
+            if let Some(heading) = heading {
+                writeln!(f, "{}:", heading)?;
+            }
+            for piece in pieces {
+                if self.staged() == Some(&piece) {
+                    writeln!(f, "{:x}: (staged)", piece.index())?;
+                } else {
+                    writeln!(f, "{:x}: {}", piece.index(), piece)?;
+                }
+            }
+        }
+
+        if let Phase::AwaitingPlacement(player, piece) = &self.phase {
+            writeln!(f)?;
+            writeln!(f, "Staged: {:x} {}", piece.index(), piece)?;
+            writeln!(f, "{} must place it", player)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, false, &Tray::default())
+    }
+}
+
+/// A game drawn with its placed pieces face down, from `Game::blindfolded`
+pub struct Blindfolded<'a>(&'a Game);
+
+impl fmt::Display for Blindfolded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write(f, true, &Tray::default())
+    }
+}
+
+/// A game drawn with its tray laid out, from `Game::arranged`
+pub struct Arranged<'a> {
+    game: &'a Game,
+    tray: &'a Tray,
+    blind: bool,
+}
+
+impl fmt::Display for Arranged<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.game.write(f, self.blind, self.tray)
+    }
+}
+
+impl fmt::Display for Threat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} shares {}, open at {}",
+            self.line,
+            self.attribute,
+            square_name(self.open_square.0, self.open_square.1)
+        )
+    }
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IllegalTransition::StageBeforePlacing => {
+                write!(f, "the staged piece must be placed first")
+            }
+            IllegalTransition::PlaceWithoutStaging => write!(f, "there is no staged piece"),
+            IllegalTransition::GameOver => write!(f, "the game is over"),
+        }
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Stage(piece) => write!(f, "{:x}", piece.index()),
+            Move::Place((row_idx, square_idx)) => {
+                write!(f, "{}", square_name(*row_idx, *square_idx))
+            }
+        }
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::Illegal(err) => write!(f, "{}", err),
+            MoveError::Unavailable(piece) => write!(f, "{} is not available to stage", piece),
+            MoveError::Occupied((row_idx, square_idx)) => {
+                write!(f, "{} is already taken", square_name(*row_idx, *square_idx))
+            }
+            MoveError::AdjacentToLast((row_idx, square_idx)) => write!(
+                f,
+                "{} is next to the last piece placed",
+                square_name(*row_idx, *square_idx)
+            ),
+            MoveError::NoTakeBack => write!(f, "there is no move to take back"),
+            MoveError::OffBoard((row_idx, square_idx)) => {
+                write!(f, "({}, {}) is off the board", row_idx, square_idx)
+            }
+            MoveError::NoQuarto => write!(f, "there is no quarto to call"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MoveError::Illegal(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resolution::Win(player, quarto) => write!(f, "{} wins with a {}", player, quarto),
+            Resolution::Resignation(player) => {
+                write!(f, "{} resigns, {} wins", player, player.toggle())
+            }
+            Resolution::Timeout(player) => {
+                write!(f, "{} ran out of time, {} wins", player, player.toggle())
+            }
+            Resolution::Draw => write!(f, "Draw, the board is full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{HumanAgent, RandomAgent};
+    use crate::{find_quarto, unplayed_pieces};
+    use proptest::prelude::*;
+
+    #[test]
+    fn stage_and_place_test() {
+        let mut game = Game::new();
+        let piece = game.pieces[3].clone();
+
+        game.stage(&piece).unwrap();
+        assert_eq!(game.staged(), Some(&piece));
+        assert_eq!(game.pieces.len(), 15);
+        assert_eq!(game.current_player(), Some(Player::B));
+
+        game.place(1, 2).unwrap();
+        assert_eq!(game.board()[1][2], Some(piece));
+        assert_eq!(game.staged(), None);
+        assert_eq!(game.current_player(), Some(Player::B));
+        assert_eq!(game.empty_squares().len(), 15);
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let staged = Piece::from_index(5).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let mut game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::B, staged),
+            Rules::default(),
+        )
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let chance = game.evaluate(Player::B, 100, &mut rng);
+        assert!(chance > 0.0 && chance < 1.0);
+        assert_eq!(game.evaluate(Player::B, 0, &mut rng), 0.5);
+
+        game.place(0, 3).unwrap();
+        assert_eq!(game.evaluate(Player::B, 100, &mut rng), 1.0);
+        assert_eq!(game.evaluate(Player::A, 100, &mut rng), 0.0);
+    }
+
+    #[test]
+    fn torus_test() {
+        let board = crate::position::parse_position("...0/1.../.4../....").unwrap();
+        let staged = Piece::from_index(5).unwrap();
+        let pieces: Vec<Piece> = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let flat = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::B, staged),
+            Rules::default(),
+        )
+        .unwrap();
+        let mut torus = flat.clone();
+        torus.set_rules(Rules {
+            torus: true,
+            ..Rules::default()
+        });
+
+        assert!(flat.threats().is_empty());
+        assert!(flat
+            .winning_squares(&Piece::from_index(5).unwrap())
+            .is_empty());
+        assert_eq!(
+            torus.winning_squares(&Piece::from_index(5).unwrap()),
+            vec![(3, 2)]
+        );
+        assert_eq!(
+            torus.threats()[0].to_string(),
+            "wrapping diagonal from d1 shares height, open at c4"
+        );
+
+        let mut flat = flat;
+        flat.place(3, 2).unwrap();
+        assert_eq!(flat.resolution(), None);
+        torus.place(3, 2).unwrap();
+        match torus.resolution() {
+            Some(Resolution::Win(Player::B, quarto)) => {
+                assert_eq!(quarto.line(), Line::WrappingDiagonal(3))
+            }
+            other => panic!("expected a wrapping win, got {:?}", other),
+        }
+
+        // A wrapping quarto set up on the board only ends a torus game
+        let board = crate::position::parse_position("...0/1.../.4../..5.").unwrap();
+        let set_up = |rules| {
+            let pieces = unplayed_pieces(&board);
+            Game::from_position(
+                board.clone(),
+                pieces,
+                Phase::AwaitingStage(Player::A),
+                rules,
+            )
+            .unwrap()
+        };
+        assert_eq!(set_up(Rules::default()).resolution(), None);
+        let torus = set_up(Rules {
+            torus: true,
+            ..Rules::default()
+        });
+        assert!(matches!(torus.resolution(), Some(Resolution::Win(..))));
+    }
+
+    #[test]
+    fn canonical_hash_test() {
+        let game = |position: &str, staged: u8| {
+            let board = crate::position::parse_position(position).unwrap();
+            let staged = Piece::from_index(staged).unwrap();
+            let pieces = unplayed_pieces(&board)
+                .into_iter()
+                .filter(|piece| *piece != staged)
+                .collect();
+            Game::from_position(
+                board,
+                pieces,
+                Phase::AwaitingPlacement(Player::B, staged),
+                Rules::default(),
+            )
+            .unwrap()
+        };
+        let hash = game("01../..4./..../....", 5).canonical_hash();
+
+        // Rotated a quarter turn, reflected, and with every piece's height
+        // and shape swapped
+        assert_eq!(game("...0/...1/..4./....", 5).canonical_hash(), hash);
+        assert_eq!(game("..10/.4../..../....", 5).canonical_hash(), hash);
+        assert_eq!(game("98../..d./..../....", 0xc).canonical_hash(), hash);
+
+        assert_ne!(game("01../4.../..../....", 5).canonical_hash(), hash);
+        assert_ne!(game("01../..4./..../....", 6).canonical_hash(), hash);
+    }
+
+    #[test]
+    fn perft_test() {
+        let counts: Vec<u64> = (0..=5).map(|depth| Game::new().perft(depth)).collect();
+        assert_eq!(counts, [1, 16, 256, 3840, 57_600, 806_400]);
+
+        // Nine of the thirteen pieces left win on d1, ending those lines
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let pieces = unplayed_pieces(&board);
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingStage(Player::A),
+            Rules::default(),
+        )
+        .unwrap();
+        assert_eq!(game.perft(2), 13 * 13);
+        assert_eq!(game.perft(3), (13 * 13 - 9) * 12);
+    }
+
+    #[test]
+    fn legal_moves_test() {
+        let mut game = Game::with_rules(Rules {
+            no_adjacent_repeat: true,
+            ..Rules::default()
+        });
+        assert_eq!(game.legal_moves().len(), 16);
+        game.stage(&Piece::from_index(0).unwrap()).unwrap();
+        game.place(1, 1).unwrap();
+        game.stage(&Piece::from_index(1).unwrap()).unwrap();
+
+        let moves = game.legal_moves();
+        assert_eq!(moves.len(), 11);
+        for mv in moves {
+            game.apply(mv.clone()).unwrap();
+            assert_eq!(game.undo(), Some((Player::A, mv)));
+            assert_eq!(game.board()[1][1], Some(Piece::from_index(0).unwrap()));
+            assert_eq!(game.staged(), Some(&Piece::from_index(1).unwrap()));
+        }
+
+        let child = game.child(Move::Place((3, 3))).unwrap();
+        assert_eq!(child.board()[3][3], Some(Piece::from_index(1).unwrap()));
+        assert_eq!(game.board()[3][3], None);
+        assert_eq!(
+            game.child(Move::Place((1, 2))).err(),
+            Some(MoveError::AdjacentToLast((1, 2)))
+        );
+
+        // A child carries only the last placement, yet plays like a full copy
+        let next = game.legal_moves()[0].clone();
+        let child = game.child(next.clone()).unwrap();
+        let mut copy = game.clone();
+        copy.apply(next).unwrap();
+        assert!(child.history().len() <= 2);
+        assert_eq!(child.legal_moves(), copy.legal_moves());
+        assert_eq!(child.canonical_hash(), copy.canonical_hash());
+
+        game.resign();
+        assert_eq!(game.legal_moves(), []);
+    }
+
+    #[test]
+    fn attribute_census_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let staged = Piece::from_index(0xf).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::A, staged),
+            Rules::default(),
+        )
+        .unwrap();
+
+        let census = game.attribute_census();
+        assert_eq!(census.len(), 8);
+        assert!(census
+            .iter()
+            .all(|count| count.unplayed + count.placed == 8));
+        let find = |name| {
+            let (attribute, value) = crate::Attribute::parse_value(name).unwrap();
+            census
+                .iter()
+                .find(|count| count.attribute == attribute && count.value == value)
+                .map(|count| (count.unplayed, count.placed))
+        };
+        assert_eq!(find("tall"), Some((5, 3)));
+        assert_eq!(find("short"), Some((8, 0)));
+        assert_eq!(find("dark"), Some((6, 2)));
+        assert_eq!(find("round"), Some((7, 1)));
+    }
+
+    #[test]
+    fn call_quarto_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let staged = Piece::from_index(5).unwrap();
+        let pieces: Vec<Piece> = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let mut game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::B, staged),
+            Rules::default(),
+        )
+        .unwrap();
+        game.set_rules(Rules {
+            call_quarto: true,
+            ..Rules::default()
+        });
+        assert_eq!(game.call_quarto(), Err(MoveError::NoQuarto));
+
+        // Nothing happens until someone calls it
+        game.place(0, 3).unwrap();
+        let quarto = game.uncalled_quarto().unwrap();
+        assert_eq!(game.phase(), &Phase::AwaitingStage(Player::B));
+
+        // Player B forgets, so Player A's agent claims it
+        let mut claimed = game.clone();
+        claimed.stage(&Piece::from_index(2).unwrap()).unwrap();
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(HumanAgent::new(0)),
+        ];
+        claimed.tick(&mut agents).unwrap();
+        assert_eq!(
+            claimed.resolution(),
+            Some(Resolution::Win(Player::A, quarto))
+        );
+
+        // Once Player A places instead, the chance is gone
+        claimed = game.clone();
+        claimed.stage(&Piece::from_index(2).unwrap()).unwrap();
+        claimed.place(3, 3).unwrap();
+        assert_eq!(claimed.uncalled_quarto(), None);
+
+        game.call_quarto().unwrap();
+        assert_eq!(game.resolution(), Some(Resolution::Win(Player::B, quarto)));
+    }
+
+    #[test]
+    fn no_adjacent_repeat_test() {
+        let mut game = Game::with_rules(Rules {
+            no_adjacent_repeat: true,
+            ..Rules::default()
+        });
+        game.stage(&Piece::from_index(0).unwrap()).unwrap();
+        game.place(1, 1).unwrap();
+        game.stage(&Piece::from_index(1).unwrap()).unwrap();
+
+        assert_eq!(game.legal_squares().len(), 11);
+        assert_eq!(game.place(1, 2), Err(MoveError::AdjacentToLast((1, 2))));
+        assert!(game
+            .to_string()
+            .starts_with("  a b c d\n1 . x . .\n2 x 0 x .\n"));
+        game.place(2, 2).unwrap();
+
+        // With every empty square next to the last piece, any of them will do
+        let board = crate::position::parse_position("5283/0cd./9a74/ef1.").unwrap();
+        let staged = Piece::from_index(6).unwrap();
+        let pool = vec![Piece::from_index(0xb).unwrap()];
+        let mut game = Game::from_position(
+            board,
+            pool,
+            Phase::AwaitingPlacement(Player::A, staged),
+            Rules::default(),
+        )
+        .unwrap();
+        game.rules.no_adjacent_repeat = true;
+        game.history.push((Player::A, Move::Place((2, 3))));
+        assert_eq!(game.legal_squares(), [(1, 3), (3, 3)]);
+
+        // Tall 4 would complete row 1 on c1, but that's next to b1
+        let mut game = Game::with_rules(Rules {
+            no_adjacent_repeat: true,
+            ..Rules::default()
+        });
+        for mv in ["0", "a1", "1", "d1", "f", "b3", "2", "b1"].iter() {
+            game.apply(game.parse_move(mv).unwrap()).unwrap();
+        }
+        let tall = Piece::from_index(4).unwrap();
+        assert_eq!(game.winning_squares(&tall), []);
+        assert_eq!(game.loses_immediately(&tall), None);
+        assert!(game.safe_pieces().contains(&&tall));
+        game.stage(&tall).unwrap();
+        assert_eq!(game.winning_squares(&tall), []);
+        assert_eq!(game.place(0, 2), Err(MoveError::AdjacentToLast((0, 2))));
+    }
+
+    #[test]
+    fn take_back_test() {
+        let mut game = Game::with_rules(Rules {
+            take_backs: 1,
+            ..Rules::default()
+        });
+        assert!(!game.can_take_back());
+
+        game.stage(&Piece::from_index(5).unwrap()).unwrap();
+        game.place(2, 1).unwrap();
+        game.stage(&Piece::from_index(0xa).unwrap()).unwrap();
+        let before = game.clone();
+        game.place(0, 0).unwrap();
+        game.stage(&Piece::from_index(3).unwrap()).unwrap();
+
+        // Player B takes back their stage, and Player A's moves since
+        assert!(game.can_take_back());
+        game.take_back().unwrap();
+        assert_eq!(game.phase(), &Phase::AwaitingStage(Player::B));
+        assert_eq!(game.board()[0][0], None);
+        assert_eq!(game.pieces().len(), 15);
+        assert_eq!(game.history(), &before.history()[..2]);
+
+        game.stage(&Piece::from_index(0xa).unwrap()).unwrap();
+        game.place(0, 0).unwrap();
+        game.stage(&Piece::from_index(3).unwrap()).unwrap();
+        assert_eq!(game.take_back(), Err(MoveError::NoTakeBack));
+        assert!(find_quarto(game.board()).is_none());
+    }
+
+    #[test]
+    fn move_log_test() {
+        let mut game = Game::new();
+        game.stage(&Piece::from_index(5).unwrap()).unwrap();
+        game.place(2, 1).unwrap();
+        game.stage(&Piece::from_index(0xa).unwrap()).unwrap();
+
+        assert_eq!(
+            game.history(),
+            &[
+                (Player::A, Move::Stage(Piece::from_index(5).unwrap())),
+                (Player::B, Move::Place((2, 1))),
+                (Player::B, Move::Stage(Piece::from_index(0xa).unwrap())),
+            ]
+        );
+        assert_eq!(
+            game.move_log(),
+            [
+                "1. Player A gave 5, Player B placed it on b3",
+                "2. Player B gave a",
+            ]
+        );
+    }
+
+    #[test]
+    fn variation_test() {
+        let mut original = Game::new();
+        for mv in ["5", "b3", "a", "c2", "3"].iter() {
+            original.apply(original.parse_move(mv).unwrap()).unwrap();
+        }
+
+        // Parting mid-turn shows the whole turn, shared half included
+        let mut branch = original.clone();
+        while branch.history().len() > 3 {
+            branch.undo();
+        }
+        for mv in ["d4", "7", "a1"].iter() {
+            branch.apply(branch.parse_move(mv).unwrap()).unwrap();
+        }
+
+        assert_eq!(
+            original.variation(&branch),
+            [
+                "2. Player B gave a, Player A placed it on d4",
+                "3. Player A gave 7, Player B placed it on a1",
+            ]
+        );
+        assert_eq!(original.variation(&original), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_moves_are_rejected_test() {
+        let mut game = Game::new();
+        let piece = game.pieces[0].clone();
+
+        assert_eq!(
+            game.place(0, 0),
+            Err(MoveError::Illegal(IllegalTransition::PlaceWithoutStaging))
+        );
+        assert_eq!(game.board()[0][0], None);
+
+        game.stage(&piece).unwrap();
+        assert_eq!(
+            game.stage(&piece),
+            Err(MoveError::Illegal(IllegalTransition::StageBeforePlacing))
+        );
+        assert_eq!(game.pieces.len(), 15);
+        assert_eq!(game.place(4, 0), Err(MoveError::OffBoard((4, 0))));
+
+        game.place(0, 0).unwrap();
+        assert_eq!(game.stage(&piece), Err(MoveError::Unavailable(piece)));
+        game.stage(&game.pieces[0].clone()).unwrap();
+        assert_eq!(game.place(0, 0), Err(MoveError::Occupied((0, 0))));
+        assert!(game.staged().is_some());
+
+        // Once it's over, that's the reason given whatever the square
+        game.resign();
+        for (row_idx, square_idx) in [(1, 1), (0, 0), (4, 0)] {
+            assert_eq!(
+                game.place(row_idx, square_idx),
+                Err(MoveError::Illegal(IllegalTransition::GameOver))
+            );
+        }
+    }
+
+    #[test]
+    fn engine_can_stage_first_test() {
+        let mut game = Game::new();
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(HumanAgent::new(0)),
+        ];
+
+        game.tick(&mut agents).unwrap();
+
+        assert!(game.staged().is_some());
+        assert_eq!(game.pieces.len(), 15);
+        assert_eq!(game.current_player(), Some(Player::B));
+    }
+
+    #[test]
+    fn loses_immediately_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let pieces = unplayed_pieces(&board);
+        let game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingStage(Player::A),
+            Rules::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            game.loses_immediately(&Piece::from_index(5).unwrap()),
+            Some((0, 3))
+        );
+        assert_eq!(
+            game.loses_immediately(&Piece::from_index(0xe).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn fork_test() {
+        // Row 1 wants a tall or solid piece and row 4 a dark or round one,
+        // and e is the only piece left that's neither
+        let board = crate::position::parse_position("014./6a../cf../3b..").unwrap();
+        let staged = Piece::from_index(9).unwrap();
+        let pieces = unplayed_pieces(&board)
+            .into_iter()
+            .filter(|piece| *piece != staged)
+            .collect();
+        let mut game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingPlacement(Player::A, staged),
+            Rules::default(),
+        )
+        .unwrap();
+        assert_eq!(game.fork(), None);
+
+        game.place(3, 2).unwrap();
+        assert_eq!(game.fork(), Piece::from_index(0xe));
+        assert_eq!(game.fork_moves(), [1]);
+
+        // Blocking either open square leaves the other to lose on
+        game.stage(&Piece::from_index(0xe).unwrap()).unwrap();
+        game.place(0, 3).unwrap();
+        assert!(game
+            .pieces()
+            .iter()
+            .all(|piece| game.loses_immediately(piece).is_some()));
+
+        assert_eq!(Game::new().fork(), None);
+    }
+
+    #[test]
+    fn threats_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let pieces = unplayed_pieces(&board);
+        let mut game = Game::from_position(
+            board,
+            pieces,
+            Phase::AwaitingStage(Player::A),
+            Rules::default(),
+        )
+        .unwrap();
+
+        // 0, 1 and 4 are all tall and solid
+        let threat = |attribute| Threat {
+            line: Line::Row(0),
+            attribute,
+            open_square: (0, 3),
+        };
+        assert_eq!(
+            game.threats(),
+            vec![threat(Attribute::Height), threat(Attribute::Density)]
+        );
+        assert_eq!(
+            game.completing_lines(&Piece::from_index(5).unwrap()),
+            game.threats()
+        );
+        assert_eq!(
+            game.completing_lines(&Piece::from_index(8).unwrap()),
+            vec![threat(Attribute::Density)]
+        );
+        assert_eq!(
+            game.completing_lines(&Piece::from_index(0xe).unwrap()),
+            Vec::new()
+        );
+
+        game.stage(&Piece::from_index(0xe).unwrap()).unwrap();
+        game.place(0, 3).unwrap();
+        assert_eq!(game.threats(), Vec::new());
+    }
+
+    #[test]
+    fn phase_transitions_test() {
+        let piece = Piece::from_index(0).unwrap();
+        let staging = Phase::AwaitingStage(Player::A);
+        let placing = Phase::AwaitingPlacement(Player::B, piece.clone());
+
+        assert_eq!(staging.after_stage(&piece), Ok(placing.clone()));
+        assert_eq!(
+            staging.after_place(None, true),
+            Err(IllegalTransition::PlaceWithoutStaging)
+        );
+        assert_eq!(
+            placing.after_stage(&piece),
+            Err(IllegalTransition::StageBeforePlacing)
+        );
+        assert_eq!(
+            placing.after_place(None, true),
+            Ok(Phase::AwaitingStage(Player::B))
+        );
+        assert_eq!(
+            placing.after_place(None, false),
+            Ok(Phase::GameOver(Resolution::Draw))
+        );
+
+        let over = placing.after_resign().unwrap();
+        assert_eq!(over, Phase::GameOver(Resolution::Resignation(Player::B)));
+        assert_eq!(over.player(), None);
+        assert_eq!(over.after_stage(&piece), Err(IllegalTransition::GameOver));
+    }
+
+    #[test]
+    fn from_position_test() {
+        let board = crate::position::parse_position("014./..../..../....").unwrap();
+        let staged = Piece::from_index(5).unwrap();
+        let phase = Phase::AwaitingPlacement(Player::B, staged.clone());
+
+        assert_eq!(
+            Game::from_position(
+                board.clone(),
+                unplayed_pieces(&board),
+                phase.clone(),
+                Rules::default()
+            )
+            .err(),
+            Some(SetupError::Duplicate(staged.clone()))
+        );
+        assert_eq!(
+            Game::from_position(board.clone(), Vec::new(), phase.clone(), Rules::default()).err(),
+            Some(SetupError::Missing(Piece::from_index(2).unwrap()))
+        );
+
+        let mut pieces = unplayed_pieces(&board);
+        pieces.retain(|piece| *piece != staged);
+        pieces.reverse();
+        let game = Game::from_position(board, pieces, phase, Rules::default()).unwrap();
+        assert_eq!(game.pieces()[0], Piece::from_index(2).unwrap());
+        assert_eq!(game.staged(), Some(&staged));
+
+        // A quarto on the board goes to whoever placed last
+        let board = crate::position::parse_position("0145/..../..../....").unwrap();
+        let staged = Piece::from_index(2).unwrap();
+        let mut pieces = unplayed_pieces(&board);
+        pieces.retain(|piece| *piece != staged);
+        let placing = Phase::AwaitingPlacement(Player::B, staged);
+        let game = Game::from_position(board.clone(), pieces, placing, Rules::default()).unwrap();
+        assert_eq!(game.resolution().and_then(|r| r.winner()), Some(Player::A));
+        let staging = Phase::AwaitingStage(Player::B);
+        let game = Game::from_position(
+            board.clone(),
+            unplayed_pieces(&board),
+            staging,
+            Rules::default(),
+        )
+        .unwrap();
+        assert_eq!(game.resolution().and_then(|r| r.winner()), Some(Player::B));
+        let quarto = match game.resolution() {
+            Some(Resolution::Win(_, quarto)) => quarto,
+            other => panic!("expected a win, got {:?}", other),
+        };
+
+        // A game given as over has to match the board
+        let win = Resolution::Win(Player::A, quarto);
+        let empty = empty_board();
+        assert_eq!(
+            Game::from_position(
+                empty.clone(),
+                all_pieces(),
+                Phase::GameOver(win),
+                Rules::default()
+            )
+            .err(),
+            Some(SetupError::Resolution(win))
+        );
+        assert_eq!(
+            Game::from_position(
+                board.clone(),
+                unplayed_pieces(&board),
+                Phase::GameOver(win),
+                Rules::default()
+            )
+            .unwrap()
+            .resolution(),
+            Some(win)
+        );
+        let draw = Phase::GameOver(Resolution::Draw);
+        assert_eq!(
+            Game::from_position(empty.clone(), all_pieces(), draw.clone(), Rules::default()).err(),
+            Some(SetupError::Resolution(Resolution::Draw))
+        );
+        // A full board in index order has a quarto along its first row
+        let mut full_board = empty.clone();
+        for (square, piece) in full_board.iter_mut().flatten().zip(all_pieces()) {
+            *square = Some(piece);
+        }
+        assert_eq!(
+            Game::from_position(full_board, Vec::new(), draw.clone(), Rules::default()).err(),
+            Some(SetupError::Resolution(Resolution::Draw))
+        );
+    }
+
+    #[test]
+    fn resignation_test() {
+        let mut game = Game::new();
+        game.stage(&game.pieces[0].clone()).unwrap();
+        game.resign();
+
+        assert_eq!(game.resolution(), Some(Resolution::Resignation(Player::B)));
+        assert_eq!(game.resolution().unwrap().winner(), Some(Player::A));
+
+        // The result is final
+        game.resign();
+        assert_eq!(game.resolution(), Some(Resolution::Resignation(Player::B)));
+        game.lose_on_time();
+        assert_eq!(game.resolution(), Some(Resolution::Resignation(Player::B)));
+
+        let mut timed = Game::new();
+        timed.lose_on_time();
+        assert_eq!(timed.resolution(), Some(Resolution::Timeout(Player::A)));
+        assert_eq!(
+            timed.resolution().unwrap().to_string(),
+            "Player A ran out of time, Player B wins"
+        );
+    }
+
+    #[test]
+    fn display_marks_staged_piece_test() {
+        let mut game = Game::new();
+        game.stage(&Piece::from_index(5).unwrap()).unwrap();
+        let shown = game.to_string();
+
+        assert!(shown.contains("4: tall light solid square\n5: (staged)\n6: "));
+        assert!(shown.ends_with("Staged: 5 tall light solid round\nPlayer B must place it\n"));
+    }
+
+    #[test]
+    fn blindfolded_hides_all_but_last_placement_test() {
+        let mut game = Game::new();
+        game.stage(&Piece::from_index(5).unwrap()).unwrap();
+        game.place(0, 0).unwrap();
+        game.stage(&Piece::from_index(0xa).unwrap()).unwrap();
+        game.place(2, 1).unwrap();
+
+        let shown = game.blindfolded().to_string();
+        assert!(shown.starts_with("  a b c d\n1 ? . . .\n2 . . . .\n3 . a . .\n"));
+        assert!(game.to_string().starts_with("  a b c d\n1 5 . . .\n"));
+    }
+
+    #[test]
+    fn square_name_test() {
+        assert_eq!(square_name(2, 1), "b3");
+        assert_eq!(parse_square("b3"), Some((2, 1)));
+        assert_eq!(parse_square("d4"), Some((3, 3)));
+        assert_eq!(parse_square("e1"), None);
+        assert_eq!(parse_square("a5"), None);
+        assert_eq!(parse_square("a12"), None);
+    }
+
+    /// Play out `choices` as alternating stage and place picks, checking
+    /// the game after every half-turn
+    fn check_random_game(choices: &[(usize, usize)]) {
+        let mut game = Game::new();
+
+        for (piece_choice, square_choice) in choices {
+            if game.resolution().is_some() {
+                break;
+            }
+
+            let before = game.clone();
+            let piece = game.pieces[piece_choice % game.pieces.len()].clone();
+            game.stage(&piece).unwrap();
+            check_undo_round_trip(&mut game);
+            assert_eq!(game.staged(), Some(&piece));
+            assert_eq!(
+                game.current_player(),
+                before.current_player().map(|player| player.toggle())
+            );
+            assert_eq!(game.board(), before.board());
+            check_piece_set(&game);
+
+            let before = game.clone();
+            let squares = game.empty_squares();
+            let (row_idx, square_idx) = squares[square_choice % squares.len()];
+            game.place(row_idx, square_idx).unwrap();
+            check_undo_round_trip(&mut game);
+            assert_eq!(game.staged(), None);
+            assert_eq!(game.board()[row_idx][square_idx].as_ref(), before.staged());
+            assert_eq!(game.empty_squares().len(), squares.len() - 1);
+            check_piece_set(&game);
+
+            // The incremental line state agrees with a full board scan
+            let scanned = find_quarto(game.board());
+            match game.resolution() {
+                Some(Resolution::Win(_, quarto)) => assert_eq!(Some(quarto), scanned),
+                _ => assert_eq!(scanned, None),
+            }
+
+            // The player who placed either wins or stages next
+            match game.resolution() {
+                Some(Resolution::Win(player, quarto)) => {
+                    assert_eq!(Some(player), before.current_player());
+                    check_quarto(game.board(), &quarto);
+                }
+                Some(_) => {}
+                None => assert_eq!(game.current_player(), before.current_player()),
+            }
+        }
+    }
+
+    /// Undoing the last move and making it again leaves the game as it was
+    fn check_undo_round_trip(game: &mut Game) {
+        let before = game.clone();
+        let (player, mv) = game.undo().unwrap();
+        assert_eq!(game.current_player(), Some(player));
+        game.apply(mv).unwrap();
+
+        assert_eq!(game.board(), before.board());
+        assert_eq!(game.pieces(), before.pieces());
+        assert_eq!(game.history(), before.history());
+        assert_eq!(game.phase(), before.phase());
+        assert_eq!(game.lines, before.lines);
+    }
+
+    /// The pool, the staged piece and the board always hold all 16 pieces
+    /// exactly once
+    fn check_piece_set(game: &Game) {
+        let mut indexes: Vec<u8> = game
+            .pieces
+            .iter()
+            .chain(game.staged())
+            .chain(game.board().iter().flatten().flatten())
+            .map(Piece::index)
+            .collect();
+        indexes.sort_unstable();
+
+        assert_eq!(indexes, (0..16).collect::<Vec<u8>>());
+    }
+
+    /// The line really is full and its pieces share the attribute
+    fn check_quarto(board: &Board, quarto: &Quarto) {
+        let pieces: Vec<&Piece> = quarto
+            .line
+            .squares()
+            .iter()
+            .map(|(row_idx, square_idx)| board[*row_idx][*square_idx].as_ref().unwrap())
+            .collect();
+        let first = pieces[0];
+
+        assert!(pieces.iter().all(|piece| match quarto.attribute {
+            Attribute::Height => piece.height == first.height,
+            Attribute::Color => piece.color == first.color,
+            Attribute::Density => piece.density == first.density,
+            Attribute::Shape => piece.shape == first.shape,
+        }));
+    }
+
+    proptest! {
+        #[test]
+        fn random_games_keep_invariants_test(
+            choices in prop::collection::vec((0..16usize, 0..16usize), 0..=16)
+        ) {
+            check_random_game(&choices);
+        }
+    }
+}
@@ -0,0 +1,1177 @@
+//! A Quarto engine: the rules, a game model and players to drive it.
+//!
+//! `game::Game` tracks a game in progress and rejects moves the rules
+//! don't allow. Players implement `agent::Agent`, and `agent::engine_agent`
+//! builds the searching ones by name.
+//!
+//! ```
+//! use quarto::game::Game;
+//! use quarto::Piece;
+//!
+//! let mut game = Game::new();
+//! game.stage(&Piece::from_index(5).unwrap()).unwrap();
+//! game.place(0, 3).unwrap();
+//! assert_eq!(game.board()[0][3], Piece::from_index(5));
+//! ```
+
+use log::debug;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::fmt;
+
+use Color::*;
+use Density::*;
+use Height::*;
+use Shape::*;
+
+pub mod agent;
+pub mod analysis;
+pub mod clock;
+pub mod editor;
+pub mod endgame;
+pub mod error;
+pub mod game;
+pub mod logging;
+pub mod position;
+pub mod rating;
+pub mod record;
+pub mod tournament;
+pub mod tray;
+pub mod uqi;
+#[cfg(test)]
+mod win_cross_check;
+
+pub fn all_pieces() -> Vec<Piece> {
+    let mut pieces = Vec::with_capacity(16);
+
+    for height in &[Tall, Short] {
+        for color in &[Dark, Light] {
+            for density in &[Solid, Hollow] {
+                for shape in &[Square, Round] {
+                    pieces.push(Piece {
+                        height: height.clone(),
+                        color: color.clone(),
+                        density: density.clone(),
+                        shape: shape.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    pieces
+}
+
+/// Pieces not yet on the board
+pub fn unplayed_pieces(board: &Board) -> Vec<Piece> {
+    all_pieces()
+        .into_iter()
+        .filter(|piece| {
+            !board
+                .iter()
+                .flatten()
+                .any(|square| square.as_ref() == Some(piece))
+        })
+        .collect()
+}
+
+const MAX_DEPTH: usize = 8;
+const SAMPLED_MOVES: usize = 20;
+
+fn play(
+    board: Board,
+    pieces: &[Piece],
+    current_player: &Player,
+    level: usize,
+    rng: &mut StdRng,
+) -> i64 {
+    path_scores(board, pieces, current_player, level, rng)
+        .iter()
+        .sum()
+}
+
+/// Score of each sampled move out of this position
+fn path_scores(
+    board: Board,
+    pieces: &[Piece],
+    current_player: &Player,
+    level: usize,
+    rng: &mut StdRng,
+) -> Vec<i64> {
+    if pieces.is_empty() || level >= MAX_DEPTH {
+        return Vec::new();
+    }
+
+    // Skip win check if there aren't enough pieces for it to be possible
+    if pieces.len() < 10 && is_win(&board) {
+        match current_player {
+            Player::A => return vec![1],
+            Player::B => return vec![-1],
+        }
+    };
+
+    let moves = sampled_moves(&board, pieces, rng);
+    let seeds = child_seeds(&moves, rng);
+
+    moves
+        .into_par_iter()
+        .take(SAMPLED_MOVES)
+        .zip(seeds)
+        .map(|((row_idx, square_idx, piece), seed)| {
+            let (board, remaining) = apply_move(&board, pieces, row_idx, square_idx, piece);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let path_score = play(
+                board,
+                &remaining,
+                &current_player.toggle(),
+                level + 1,
+                &mut rng,
+            );
+
+            if level < 3 {
+                debug!(
+                    target: logging::ENGINE,
+                    "level {}: {} on {} scored {}",
+                    level,
+                    piece,
+                    game::square_name(row_idx, square_idx),
+                    path_score
+                );
+            }
+
+            path_score
+        })
+        .collect()
+}
+
+/// Legal placements in random order
+fn sampled_moves<'a>(
+    board: &Board,
+    pieces: &'a [Piece],
+    rng: &mut impl Rng,
+) -> Vec<(usize, usize, &'a Piece)> {
+    // The opponent picks the piece we place, and won't hand over one that
+    // wins on the spot if it can help it
+    let candidates: Vec<&Piece> = if pieces.len() < 10 {
+        safe_pieces(board, pieces)
+    } else {
+        pieces.iter().collect()
+    };
+
+    let range = 0..board.len();
+    let mut moves: Vec<(usize, usize, &Piece)> = range
+        .clone()
+        .flat_map(|row_idx| range.clone().map(move |square_idx| (row_idx, square_idx)))
+        .filter(|(row_idx, square_idx)| board[*row_idx][*square_idx].is_none())
+        .flat_map(|(row_idx, square_idx)| {
+            candidates
+                .iter()
+                .map(move |piece| (row_idx, square_idx, *piece))
+        })
+        .collect();
+    moves.shuffle(rng);
+
+    moves
+}
+
+/// A seed for each move that will be searched, drawn up front so the
+/// results don't depend on how rayon schedules the searches
+fn child_seeds<T>(moves: &[T], rng: &mut impl Rng) -> Vec<u64> {
+    moves
+        .iter()
+        .take(SAMPLED_MOVES)
+        .map(|_| rng.gen())
+        .collect()
+}
+
+fn apply_move(
+    board: &Board,
+    pieces: &[Piece],
+    row_idx: usize,
+    square_idx: usize,
+    piece: &Piece,
+) -> (Board, Vec<Piece>) {
+    let remaining = pieces.iter().filter(|p| *p != piece).cloned().collect();
+    let mut board = board.clone();
+    board[row_idx][square_idx].replace(piece.clone());
+
+    (board, remaining)
+}
+
+/// Sample mean and spread of a candidate's path scores
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    samples: usize,
+    mean: f64,
+    variance: f64,
+}
+
+impl Estimate {
+    fn from_scores(scores: &[i64]) -> Self {
+        let scores: Vec<f64> = scores.iter().map(|score| *score as f64).collect();
+
+        Estimate::from_samples(&scores)
+    }
+
+    fn from_samples(scores: &[f64]) -> Self {
+        let samples = scores.len();
+        if samples == 0 {
+            return Estimate {
+                samples,
+                mean: 0.0,
+                variance: 0.0,
+            };
+        }
+
+        let mean = scores.iter().sum::<f64>() / samples as f64;
+        let variance = if samples > 1 {
+            scores
+                .iter()
+                .map(|score| (score - mean).powi(2))
+                .sum::<f64>()
+                / (samples - 1) as f64
+        } else {
+            0.0
+        };
+
+        Estimate {
+            samples,
+            mean,
+            variance,
+        }
+    }
+
+    /// Half-width of the 95% confidence interval around the mean
+    fn margin(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+
+        1.96 * (self.variance / self.samples as f64).sqrt()
+    }
+
+    fn lower_bound(&self) -> f64 {
+        self.mean - self.margin()
+    }
+
+    fn upper_bound(&self) -> f64 {
+        self.mean + self.margin()
+    }
+}
+
+pub struct Candidate<'a> {
+    row_idx: usize,
+    square_idx: usize,
+    piece: &'a Piece,
+    estimate: Estimate,
+}
+
+impl fmt::Display for Candidate<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at ({}, {}): {:.2} \u{b1} {:.2} over {} samples",
+            self.piece,
+            self.row_idx,
+            self.square_idx,
+            self.estimate.mean,
+            self.estimate.margin(),
+            self.estimate.samples
+        )
+    }
+}
+
+/// Score a sample of moves from the root, keeping each move's spread of
+/// outcomes rather than just its total
+pub fn analyze<'a>(
+    board: &Board,
+    pieces: &'a [Piece],
+    current_player: &Player,
+    rng: &mut StdRng,
+) -> Vec<Candidate<'a>> {
+    let moves = sampled_moves(board, pieces, rng);
+    let seeds = child_seeds(&moves, rng);
+
+    moves
+        .into_par_iter()
+        .take(SAMPLED_MOVES)
+        .zip(seeds)
+        .map(|((row_idx, square_idx, piece), seed)| {
+            let (board, remaining) = apply_move(board, pieces, row_idx, square_idx, piece);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let scores = path_scores(board, &remaining, &current_player.toggle(), 2, &mut rng);
+
+            Candidate {
+                row_idx,
+                square_idx,
+                piece,
+                estimate: Estimate::from_scores(&scores),
+            }
+        })
+        .collect()
+}
+
+/// Pick the candidate whose confidence interval is most favorable for the
+/// player, so a move that looks good on a few lucky samples doesn't win
+/// out over one that is reliably good. Scores favor `Player::A`.
+pub fn best_candidate<'a, 'b>(
+    candidates: &'b [Candidate<'a>],
+    current_player: &Player,
+) -> Option<&'b Candidate<'a>> {
+    match current_player {
+        Player::A => candidates.iter().max_by(|a, b| {
+            a.estimate
+                .lower_bound()
+                .total_cmp(&b.estimate.lower_bound())
+        }),
+        Player::B => candidates.iter().min_by(|a, b| {
+            a.estimate
+                .upper_bound()
+                .total_cmp(&b.estimate.upper_bound())
+        }),
+    }
+}
+
+pub fn empty_board() -> Board {
+    let row = [None, None, None, None];
+
+    [row.clone(), row.clone(), row.clone(), row.clone()]
+}
+
+/// Squares indexed by row, then column
+pub type Board = [[Option<Piece>; 4]; 4];
+
+/// A square as (row, column), both counting from 0
+pub type Coord = (usize, usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Piece {
+    height: Height,
+    color: Color,
+    density: Density,
+    shape: Shape,
+}
+
+impl Piece {
+    pub fn new(height: Height, color: Color, density: Density, shape: Shape) -> Self {
+        Piece {
+            height,
+            color,
+            density,
+            shape,
+        }
+    }
+
+    pub fn height(&self) -> &Height {
+        &self.height
+    }
+
+    pub fn color(&self) -> &Color {
+        &self.color
+    }
+
+    pub fn density(&self) -> &Density {
+        &self.density
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// Position of the piece in `all_pieces`, from 0 to 15
+    pub fn index(&self) -> u8 {
+        let height = if self.height == Tall { 0 } else { 8 };
+        let color = if self.color == Dark { 0 } else { 4 };
+        let density = if self.density == Solid { 0 } else { 2 };
+        let shape = if self.shape == Square { 0 } else { 1 };
+
+        height + color + density + shape
+    }
+
+    /// The piece with the given `index`, or `None` past 15
+    pub fn from_index(index: u8) -> Option<Piece> {
+        all_pieces().into_iter().nth(index as usize)
+    }
+
+    /// Which of `attribute`'s two values the piece has, as an index into
+    /// `Attribute::values`
+    pub fn value(&self, attribute: Attribute) -> usize {
+        let first = match attribute {
+            Attribute::Height => self.height == Tall,
+            Attribute::Color => self.color == Dark,
+            Attribute::Density => self.density == Solid,
+            Attribute::Shape => self.shape == Square,
+        };
+
+        if first {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Read a piece written as the hex digit of its index, the way pieces
+    /// are named everywhere from prompts to record files
+    pub fn parse(text: &str) -> Option<Piece> {
+        u8::from_str_radix(text.trim(), 16)
+            .ok()
+            .and_then(Piece::from_index)
+    }
+}
+
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.height, self.color, self.density, self.shape
+        )
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tall => write!(f, "tall"),
+            Short => write!(f, "short"),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dark => write!(f, "dark"),
+            Light => write!(f, "light"),
+        }
+    }
+}
+
+impl fmt::Display for Density {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Solid => write!(f, "solid"),
+            Hollow => write!(f, "hollow"),
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Round => write!(f, "round"),
+            Square => write!(f, "square"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Height {
+    Tall,
+    Short,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Color {
+    Dark,
+    Light,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Density {
+    Solid,
+    Hollow,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Round,
+    Square,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Player {
+    A,
+    B,
+}
+
+impl Player {
+    /// 0 for A and 1 for B, for indexing per-player arrays
+    pub fn index(&self) -> usize {
+        match self {
+            Player::A => 0,
+            Player::B => 1,
+        }
+    }
+
+    /// The other player
+    pub fn toggle(&self) -> Self {
+        match self {
+            Player::A => Player::B,
+            Player::B => Player::A,
+        }
+    }
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Player::A => write!(f, "Player A"),
+            Player::B => write!(f, "Player B"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attribute {
+    Height,
+    Color,
+    Density,
+    Shape,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line {
+    Row(usize),
+    Column(usize),
+    Diagonal,
+    AntiDiagonal,
+    /// A diagonal starting on the top row at the given column, which runs
+    /// off the right edge and wraps to the left. Only a line on a torus.
+    WrappingDiagonal(usize),
+    /// An anti-diagonal starting on the top row at the given column, which
+    /// runs off the left edge and wraps to the right. Only a line on a
+    /// torus.
+    WrappingAntiDiagonal(usize),
+}
+
+impl Line {
+    /// Every line on the board: rows, then columns, then the diagonals
+    pub fn all() -> [Line; 10] {
+        [
+            Line::Row(0),
+            Line::Row(1),
+            Line::Row(2),
+            Line::Row(3),
+            Line::Column(0),
+            Line::Column(1),
+            Line::Column(2),
+            Line::Column(3),
+            Line::Diagonal,
+            Line::AntiDiagonal,
+        ]
+    }
+
+    /// The diagonals that only make a line when the board wraps around its
+    /// edges. Rows and columns already span the board, so wrapping adds
+    /// nothing to them.
+    pub fn wrapping() -> [Line; 6] {
+        [
+            Line::WrappingDiagonal(1),
+            Line::WrappingDiagonal(2),
+            Line::WrappingDiagonal(3),
+            Line::WrappingAntiDiagonal(0),
+            Line::WrappingAntiDiagonal(1),
+            Line::WrappingAntiDiagonal(2),
+        ]
+    }
+
+    pub fn wraps(&self) -> bool {
+        matches!(
+            self,
+            Line::WrappingDiagonal(_) | Line::WrappingAntiDiagonal(_)
+        )
+    }
+
+    /// Name of the kind of line, without saying which row or column
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Line::Row(_) => "row",
+            Line::Column(_) => "column",
+            Line::Diagonal => "diagonal",
+            Line::AntiDiagonal => "anti-diagonal",
+            Line::WrappingDiagonal(_) => "wrapping diagonal",
+            Line::WrappingAntiDiagonal(_) => "wrapping anti-diagonal",
+        }
+    }
+
+    pub fn squares(&self) -> [Coord; 4] {
+        match *self {
+            Line::Row(row) => [(row, 0), (row, 1), (row, 2), (row, 3)],
+            Line::Column(col) => [(0, col), (1, col), (2, col), (3, col)],
+            Line::Diagonal => [(0, 0), (1, 1), (2, 2), (3, 3)],
+            Line::AntiDiagonal => [(0, 3), (1, 2), (2, 1), (3, 0)],
+            Line::WrappingDiagonal(col) => [
+                (0, col),
+                (1, (col + 1) % 4),
+                (2, (col + 2) % 4),
+                (3, (col + 3) % 4),
+            ],
+            Line::WrappingAntiDiagonal(col) => [
+                (0, col),
+                (1, (col + 3) % 4),
+                (2, (col + 2) % 4),
+                (3, (col + 1) % 4),
+            ],
+        }
+    }
+}
+
+/// A completed line and the attribute its pieces share
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quarto {
+    line: Line,
+    attribute: Attribute,
+}
+
+impl Quarto {
+    pub fn line(&self) -> Line {
+        self.line
+    }
+
+    pub fn attribute(&self) -> Attribute {
+        self.attribute
+    }
+}
+
+impl Attribute {
+    /// Every attribute, in the order pieces are described
+    pub fn all() -> [Attribute; 4] {
+        [
+            Attribute::Height,
+            Attribute::Color,
+            Attribute::Density,
+            Attribute::Shape,
+        ]
+    }
+
+    /// The attribute's two values by name, the one a piece of index 0 has
+    /// first
+    pub fn values(&self) -> [&'static str; 2] {
+        match self {
+            Attribute::Height => ["tall", "short"],
+            Attribute::Color => ["dark", "light"],
+            Attribute::Density => ["solid", "hollow"],
+            Attribute::Shape => ["square", "round"],
+        }
+    }
+
+    /// Read an attribute by name, like `height`
+    pub fn parse(text: &str) -> Option<Attribute> {
+        Attribute::all()
+            .iter()
+            .copied()
+            .find(|attribute| attribute.to_string() == text.trim())
+    }
+
+    /// Read a value by name, like `hollow`, as its attribute and its index
+    /// into `values`
+    pub fn parse_value(text: &str) -> Option<(Attribute, usize)> {
+        Attribute::all().iter().find_map(|attribute| {
+            let value = attribute.values().iter().position(|v| *v == text.trim())?;
+            Some((*attribute, value))
+        })
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Attribute::Height => "height",
+            Attribute::Color => "color",
+            Attribute::Density => "density",
+            Attribute::Shape => "shape",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Row(row) => write!(f, "row {}", row + 1),
+            Line::Column(col) => write!(f, "column {}", col + 1),
+            Line::Diagonal => write!(f, "diagonal"),
+            Line::AntiDiagonal => write!(f, "anti-diagonal"),
+            Line::WrappingDiagonal(col) | Line::WrappingAntiDiagonal(col) => {
+                write!(f, "{} from {}1", self.kind(), (b'a' + *col as u8) as char)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Quarto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "quarto on {} by {}", self.line, self.attribute)
+    }
+}
+
+pub fn is_win(board: &Board) -> bool {
+    find_quarto(board).is_some()
+}
+
+pub fn find_quarto(board: &Board) -> Option<Quarto> {
+    Line::all().iter().find_map(|line| {
+        line_win(board, line).map(|attribute| Quarto {
+            line: *line,
+            attribute,
+        })
+    })
+}
+
+/// Pieces that can be handed to the opponent without letting them win on
+/// their next placement. If every piece loses, the one with the fewest
+/// winning squares is the only candidate.
+pub fn safe_pieces<'a>(board: &Board, pieces: &'a [Piece]) -> Vec<&'a Piece> {
+    let threats: Vec<(&Piece, usize)> = pieces
+        .iter()
+        .map(|piece| (piece, winning_squares(board, piece).len()))
+        .collect();
+
+    let safe: Vec<&Piece> = threats
+        .iter()
+        .filter(|(_, count)| *count == 0)
+        .map(|(piece, _)| *piece)
+        .collect();
+
+    if !safe.is_empty() {
+        return safe;
+    }
+
+    threats
+        .iter()
+        .min_by_key(|(_, count)| *count)
+        .map(|(piece, _)| *piece)
+        .into_iter()
+        .collect()
+}
+
+/// Empty squares where placing `piece` would complete a line
+pub fn winning_squares(board: &Board, piece: &Piece) -> Vec<Coord> {
+    let mut squares = Vec::new();
+
+    for row in 0..board.len() {
+        for col in 0..board.len() {
+            if board[row][col].is_some() {
+                continue;
+            }
+
+            let mut board = board.clone();
+            board[row][col] = Some(piece.clone());
+
+            if winning_square(&board, row, col) {
+                squares.push((row, col));
+            }
+        }
+    }
+
+    squares
+}
+
+/// Check only the lines running through the given square
+fn winning_square(board: &Board, row: usize, col: usize) -> bool {
+    Line::all()
+        .iter()
+        .filter(|line| line.squares().contains(&(row, col)))
+        .any(|line| line_win(board, line).is_some())
+}
+
+fn line_win(board: &Board, line: &Line) -> Option<Attribute> {
+    let pieces = line
+        .squares()
+        .map(|(row_idx, square_idx)| board[row_idx][square_idx].as_ref());
+
+    shared_attribute(&pieces)
+}
+
+pub fn shared_attribute(pieces: &[Option<&Piece>; 4]) -> Option<Attribute> {
+    match &pieces[0] {
+        Some(first_piece) => {
+            let mut color = Some(&first_piece.color);
+            let mut shape = Some(&first_piece.shape);
+            let mut density = Some(&first_piece.density);
+            let mut height = Some(&first_piece.height);
+            for piece in pieces.iter().skip(1) {
+                match piece {
+                    Some(piece) => {
+                        if color != Some(&piece.color) {
+                            color.take();
+                        }
+                        if height != Some(&piece.height) {
+                            height.take();
+                        }
+                        if shape != Some(&piece.shape) {
+                            shape.take();
+                        }
+                        if density != Some(&piece.density) {
+                            density.take();
+                        }
+                    }
+                    None => return None,
+                }
+
+                if color.is_none() && shape.is_none() && density.is_none() && height.is_none() {
+                    return None;
+                }
+            }
+
+            if height.is_some() {
+                Some(Attribute::Height)
+            } else if color.is_some() {
+                Some(Attribute::Color)
+            } else if density.is_some() {
+                Some(Attribute::Density)
+            } else {
+                Some(Attribute::Shape)
+            }
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tall_win_test() {
+        let mut board = empty_board();
+        board[0][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[0][1] = Some(Piece {
+            height: Tall,
+            color: Light,
+            density: Solid,
+            shape: Round,
+        });
+        board[0][2] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Hollow,
+            shape: Round,
+        });
+        board[0][3] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Square,
+        });
+
+        assert!(is_win(&board));
+    }
+
+    #[test]
+    fn dark_win_test() {
+        let mut board = empty_board();
+        board[0][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[1][0] = Some(Piece {
+            height: Short,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[2][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Hollow,
+            shape: Round,
+        });
+        board[3][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Square,
+        });
+
+        assert!(is_win(&board));
+    }
+
+    #[test]
+    fn diagonal_win_test() {
+        let mut board = empty_board();
+        board[0][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[1][1] = Some(Piece {
+            height: Short,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[2][2] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Hollow,
+            shape: Round,
+        });
+        board[3][3] = Some(Piece {
+            height: Tall,
+            color: Light,
+            density: Solid,
+            shape: Round,
+        });
+
+        assert!(is_win(&board));
+    }
+
+    #[test]
+    fn empty_is_not_win_test() {
+        let board = empty_board();
+
+        assert!(!is_win(&board));
+    }
+
+    #[test]
+    fn quarto_reason_test() {
+        let mut board = empty_board();
+        board[0][3] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[1][2] = Some(Piece {
+            height: Short,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[2][1] = Some(Piece {
+            height: Tall,
+            color: Light,
+            density: Hollow,
+            shape: Round,
+        });
+        board[3][0] = Some(Piece {
+            height: Short,
+            color: Light,
+            density: Solid,
+            shape: Round,
+        });
+
+        let quarto = find_quarto(&board).unwrap();
+        assert_eq!(
+            quarto,
+            Quarto {
+                line: Line::AntiDiagonal,
+                attribute: Attribute::Shape,
+            }
+        );
+        assert_eq!(quarto.to_string(), "quarto on anti-diagonal by shape");
+    }
+
+    #[test]
+    fn quarto_row_reason_test() {
+        let mut board = threatened_board();
+        board[1][3] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Square,
+        });
+
+        assert_eq!(
+            find_quarto(&board).unwrap().to_string(),
+            "quarto on row 2 by color"
+        );
+    }
+
+    #[test]
+    fn estimate_test() {
+        let estimate = Estimate::from_scores(&[1, -1, 1, -1]);
+
+        assert_eq!(estimate.samples, 4);
+        assert_eq!(estimate.mean, 0.0);
+        assert!((estimate.variance - 4.0 / 3.0).abs() < 1e-9);
+        assert!((estimate.margin() - 1.96 * (1.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!(Estimate::from_scores(&[]).margin(), 0.0);
+    }
+
+    #[test]
+    fn best_candidate_prefers_reliable_scores_test() {
+        let piece = Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        };
+        let candidates = vec![
+            Candidate {
+                row_idx: 0,
+                square_idx: 0,
+                piece: &piece,
+                estimate: Estimate::from_scores(&[8, -4]),
+            },
+            Candidate {
+                row_idx: 1,
+                square_idx: 1,
+                piece: &piece,
+                estimate: Estimate::from_scores(&[1, 1, 1, 1]),
+            },
+            Candidate {
+                row_idx: 2,
+                square_idx: 2,
+                piece: &piece,
+                estimate: Estimate::from_scores(&[-1, -1, -1, -1]),
+            },
+        ];
+
+        let best = best_candidate(&candidates, &Player::A).unwrap();
+        assert_eq!(best.row_idx, 1);
+
+        let best = best_candidate(&candidates, &Player::B).unwrap();
+        assert_eq!(best.row_idx, 2);
+    }
+
+    fn threatened_board() -> Board {
+        let mut board = empty_board();
+        // Row 0 only shares Tall
+        board[0][0] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Round,
+        });
+        board[0][1] = Some(Piece {
+            height: Tall,
+            color: Light,
+            density: Hollow,
+            shape: Square,
+        });
+        board[0][2] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Hollow,
+            shape: Round,
+        });
+        // Row 1 only shares Dark
+        board[1][0] = Some(Piece {
+            height: Short,
+            color: Dark,
+            density: Solid,
+            shape: Square,
+        });
+        board[1][1] = Some(Piece {
+            height: Tall,
+            color: Dark,
+            density: Hollow,
+            shape: Square,
+        });
+        board[1][2] = Some(Piece {
+            height: Short,
+            color: Dark,
+            density: Hollow,
+            shape: Round,
+        });
+
+        board
+    }
+
+    #[test]
+    fn piece_parse_test() {
+        for piece in all_pieces() {
+            let text = format!("{:x}", piece.index());
+            assert_eq!(Piece::parse(&text), Some(piece));
+        }
+        assert_eq!(Piece::parse(" a\n"), Piece::from_index(10));
+        assert_eq!(Piece::parse("10"), None);
+        assert_eq!(Piece::parse("g"), None);
+    }
+
+    #[test]
+    fn attribute_values_test() {
+        for piece in all_pieces() {
+            let values: Vec<&str> = Attribute::all()
+                .iter()
+                .map(|attribute| attribute.values()[piece.value(*attribute)])
+                .collect();
+            assert_eq!(values.join(" "), piece.to_string());
+        }
+        assert_eq!(Attribute::parse("density\n"), Some(Attribute::Density));
+        assert_eq!(Attribute::parse_value("round"), Some((Attribute::Shape, 1)));
+        assert_eq!(Attribute::parse_value("blue"), None);
+    }
+
+    #[test]
+    fn winning_squares_test() {
+        let board = threatened_board();
+        let tall_dark = Piece {
+            height: Tall,
+            color: Dark,
+            density: Solid,
+            shape: Square,
+        };
+        let short_light = Piece {
+            height: Short,
+            color: Light,
+            density: Hollow,
+            shape: Round,
+        };
+
+        assert_eq!(winning_squares(&board, &tall_dark), vec![(0, 3), (1, 3)]);
+        assert!(winning_squares(&board, &short_light).is_empty());
+    }
+
+    #[test]
+    fn safe_pieces_excludes_winning_pieces_test() {
+        let board = threatened_board();
+        let pieces = vec![
+            Piece {
+                height: Tall,
+                color: Dark,
+                density: Solid,
+                shape: Square,
+            },
+            Piece {
+                height: Short,
+                color: Light,
+                density: Hollow,
+                shape: Round,
+            },
+            Piece {
+                height: Tall,
+                color: Light,
+                density: Solid,
+                shape: Round,
+            },
+        ];
+
+        assert!(safe_pieces(&board, &pieces) == vec![&pieces[1]]);
+    }
+
+    #[test]
+    fn safe_pieces_picks_fewest_threats_when_all_lose_test() {
+        let board = threatened_board();
+        let pieces = vec![
+            Piece {
+                height: Tall,
+                color: Dark,
+                density: Solid,
+                shape: Square,
+            },
+            Piece {
+                height: Tall,
+                color: Light,
+                density: Solid,
+                shape: Round,
+            },
+        ];
+
+        assert!(safe_pieces(&board, &pieces) == vec![&pieces[1]]);
+    }
+}
@@ -0,0 +1,16 @@
+//! Diagnostics from the engine and the terminal front end. Messages go
+//! through the `log` crate under one of the targets here, so they can be
+//! turned on a target at a time, as with `RUST_LOG=quarto::engine=debug`.
+//! The binary writes them to stderr, and only warnings unless asked.
+
+/// Searches: what they looked at and what they found
+pub const ENGINE: &str = "quarto::engine";
+
+/// The terminal front end: moves played and time taken
+pub const UI: &str = "quarto::ui";
+
+/// Write log messages to stderr, filtered by `RUST_LOG`, or warnings and
+/// errors only if it isn't set
+pub fn init() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+}
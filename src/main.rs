@@ -1,333 +1,1003 @@
-use rand::seq::SliceRandom;
-use rayon::prelude::*;
-
-use Color::*;
-use Density::*;
-use Height::*;
-use Shape::*;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::process;
+use std::time::Instant;
+
+use log::{debug, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use quarto::agent::{self, AdaptiveAgent, Agent, HandicappedAgent, HumanAgent};
+use quarto::clock::{self, Clock, TimeControl};
+use quarto::editor::{self, Command, Editor};
+use quarto::error::Error;
+use quarto::game::{Game, Move, Phase, Resolution, Rules};
+use quarto::logging;
+use quarto::rating::{self, Profile};
+use quarto::tournament::Match;
+use quarto::Color::{Dark, Light};
+use quarto::Density::{Hollow, Solid};
+use quarto::Height::{Short, Tall};
+use quarto::Shape::{Round, Square};
+use quarto::{
+    all_pieces, analyze, best_candidate, empty_board, position, record, tournament,
+    unplayed_pieces, uqi, Attribute, Line, Player,
+};
+
+const USAGE: &str = "Usage:
+  quarto [scenario]                          analyze the opening or a named scenario
+  quarto play [opponent] [first]             play against an engine agent, with
+                                             human, machine or random staging first;
+                                             the adaptive opponent adjusts its strength
+                                             to keep games close
+  quarto match [opponent] [games]            play a best-of-games match against an
+                                             engine agent, taking turns staging first
+  quarto tournament <first> <second> [games] pit two engine agents against each other
+  quarto simulate <a> <b> [games] [file]     play engine agents against each other in
+                                             parallel, reporting the first stager's
+                                             advantage, game length and forks, and
+                                             writing every game to a .csv or .json file
+  quarto perft <depth> [position]            count the move sequences up to depth
+                                             half-turns long, to check move generation
+  quarto rules                               explain the rules and the pieces
+  quarto uqi                                 talk to another program over stdin and
+                                             stdout, see the uqi module docs
+  quarto import <file>                       replay the games recorded in a file
+  quarto history <file> [terms]              list the games in a record file, keeping
+                                             those matching key=value terms such as
+                                             player=minimax or result=1-0, ordered by
+                                             sort=date or sort=-moves
+  quarto replay <file> <number> [opponent]   step through one game from the history,
+                                             answering branch to play on against an
+                                             engine agent from any point
+  quarto rating <file>                       show the rating kept in a profile file
+  quarto edit [opponent]                     set up a position by hand, then evaluate
+                                             it or play on from it
+
+Options:
+  --seed <n>                                 seed the engine to repeat an earlier run
+  --training                                 warn before staging a piece that loses
+  --threats                                  list lines one piece short of a quarto
+  --confirm                                  preview each placement before making it
+  --log                                      list the moves so far during play
+  --eval                                     show the engine's estimate of your chances
+  --census                                   count the pieces of each attribute value
+                                             left to play and on the board
+  --explain                                  after each engine move, show the options
+                                             it weighed and why it chose as it did
+  --export <file>                            add each game played to a record file
+  --rating <file>                            rate each game played in a profile file
+  --blind <peeks>                            draw placed pieces face down once the next
+                                             piece is placed, allowing this many peeks
+                                             at the board by answering peek
+  --clock <control>                          play timed games, losing when time runs out,
+                                             with minutes+seconds of increment such as
+                                             1+0, 3+2 or 5+0
+  --no-adjacent                              forbid placing next to the last piece placed
+  --call-quarto                              only win a quarto by calling it, answering
+                                             quarto at the next prompt
+  --torus                                    let lines wrap around the edges of the
+                                             board, adding six broken diagonals
+  --handicap <list>                          hold the engine back, with any of threats
+                                             (it announces them), undo (you may take
+                                             back one turn a game) and first-stage (its
+                                             first piece to stage is random)
+
+Environment:
+  RUST_LOG=<filter>                          log diagnostics to stderr, such as
+                                             quarto::engine=debug for what the engine
+                                             searches or quarto::ui for time per move";
+
+/// Playouts behind the editor's evaluation
+const EVAL_PLAYOUTS: usize = 1000;
 
 fn main() {
-    let mut pieces = Vec::with_capacity(16);
-
-    for height in &[Tall, Short] {
-        for color in &[Dark, Light] {
-            for density in &[Solid, Hollow] {
-                for shape in &[Square, Round] {
-                    pieces.push(Piece {
-                        height: height.clone(),
-                        color: color.clone(),
-                        density: density.clone(),
-                        shape: shape.clone(),
-                    });
-                }
+    logging::init();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let seed: u64 = match take_option(&mut args, "--seed") {
+        Some(seed) => seed.parse().unwrap_or_else(|_| {
+            eprintln!("Expected a number after --seed");
+            process::exit(1);
+        }),
+        None => rand::random(),
+    };
+    info!(target: logging::UI, "seed {}", seed);
+
+    let mut human = HumanAgent::new(seed.wrapping_add(2));
+    human.training = take_flag(&mut args, "--training");
+    human.show_threats = take_flag(&mut args, "--threats");
+    human.confirm_placements = take_flag(&mut args, "--confirm");
+    human.show_log = take_flag(&mut args, "--log");
+    human.show_eval = take_flag(&mut args, "--eval");
+    human.show_census = take_flag(&mut args, "--census");
+    if let Some(peeks) = take_option(&mut args, "--blind") {
+        human.blind = true;
+        human.peeks = peeks.parse().unwrap_or_else(|_| {
+            eprintln!("Expected a number of peeks after --blind");
+            process::exit(1);
+        });
+        if human.show_threats || human.show_log {
+            eprintln!("--threats and --log give the board away, so they can't go with --blind");
+            process::exit(1);
+        }
+    }
+
+    let handicaps = take_option(&mut args, "--handicap").unwrap_or_default();
+    let handicaps: Vec<&str> = handicaps.split(',').filter(|h| !h.is_empty()).collect();
+    if let Some(unknown) = handicaps
+        .iter()
+        .find(|h| !["threats", "undo", "first-stage"].contains(h))
+    {
+        eprintln!(
+            "Unknown handicap {}, expected threats, undo or first-stage",
+            unknown
+        );
+        process::exit(1);
+    }
+    let rules = Rules {
+        no_adjacent_repeat: take_flag(&mut args, "--no-adjacent"),
+        take_backs: if handicaps.contains(&"undo") { 1 } else { 0 },
+        call_quarto: take_flag(&mut args, "--call-quarto"),
+        torus: take_flag(&mut args, "--torus"),
+    };
+    let export = take_option(&mut args, "--export");
+    let rating = take_option(&mut args, "--rating");
+    let clock = take_option(&mut args, "--clock").map(|control| {
+        TimeControl::parse(&control).unwrap_or_else(|| {
+            eprintln!(
+                "Unknown time control {}, expected minutes+seconds like {}",
+                control,
+                clock::PRESETS.join(", ")
+            );
+            process::exit(1);
+        })
+    });
+    let session = Session {
+        handicaps: &handicaps,
+        export: export.as_deref(),
+        rating: rating.as_deref(),
+        explain: take_flag(&mut args, "--explain"),
+        clock,
+        seed,
+    };
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] => analyze_position(None, seed),
+        ["play"] => play_against(
+            "montecarlo",
+            "human",
+            human,
+            Game::with_rules(rules),
+            &session,
+        ),
+        ["play", opponent] => {
+            play_against(opponent, "human", human, Game::with_rules(rules), &session)
+        }
+        ["play", opponent, first] => {
+            play_against(opponent, first, human, Game::with_rules(rules), &session)
+        }
+        ["match"] => play_match("montecarlo", "5", human, rules, &session),
+        ["match", opponent] => play_match(opponent, "5", human, rules, &session),
+        ["match", opponent, games] => play_match(opponent, games, human, rules, &session),
+        ["edit"] => edit_position("montecarlo", human, rules, &session),
+        ["edit", opponent] => edit_position(opponent, human, rules, &session),
+        ["rating", path] => print_profile(path),
+        ["tournament", first, second] => run_tournament(first, second, "20", seed),
+        ["tournament", first, second, games] => run_tournament(first, second, games, seed),
+        ["perft", depth] => run_perft(depth, None, rules),
+        ["perft", depth, position] => run_perft(depth, Some(position), rules),
+        ["simulate", first, second] => run_simulation(first, second, "1000", None, rules, seed),
+        ["simulate", first, second, games] => {
+            run_simulation(first, second, games, None, rules, seed)
+        }
+        ["simulate", first, second, games, out] => {
+            run_simulation(first, second, games, Some(out), rules, seed)
+        }
+        ["rules"] => print_rules(),
+        ["import", path] => import_games(path),
+        ["history", path, terms @ ..] => show_history(path, terms),
+        ["replay", path, number] => replay_game(path, number, "montecarlo", human, &session),
+        ["replay", path, number, opponent] => replay_game(path, number, opponent, human, &session),
+        ["uqi"] => {
+            let stdin = io::stdin();
+            if let Err(err) = uqi::run(stdin.lock(), io::stdout(), seed) {
+                eprintln!("{}", err);
+                process::exit(1);
             }
         }
+        [scenario] => analyze_position(Some(scenario), seed),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+/// Remove `option` and the value after it from the arguments, returning
+/// the value. Exits if the value is missing.
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let idx = args.iter().position(|arg| arg == option)?;
+    if idx + 1 >= args.len() {
+        eprintln!("Expected a value after {}", option);
+        process::exit(1);
     }
 
-    let current_player = Player::A;
-    play(empty_board(), &pieces, &current_player, 1);
+    let value = args.remove(idx + 1);
+    args.remove(idx);
+    Some(value)
+}
+
+/// Remove `flag` from the arguments, returning whether it was there
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(idx) => {
+            args.remove(idx);
+            true
+        }
+        None => false,
+    }
 }
 
-const MAX_DEPTH: usize = 8;
+/// Print the engine's view of the opening, or of a named scenario
+fn analyze_position(scenario: Option<&str>, seed: u64) {
+    let (board, pieces) = match scenario {
+        Some(name) => match position::scenario(name) {
+            Some(scenario) => {
+                println!("{}", scenario.description);
+                let board = position::parse_position(scenario.position)
+                    .expect("scenario positions are checked by tests");
+                let pieces = unplayed_pieces(&board);
+                (board, pieces)
+            }
+            None => {
+                eprintln!("Unknown scenario {}, expected one of:", name);
+                for scenario in position::SCENARIOS {
+                    eprintln!("  {}: {}", scenario.name, scenario.description);
+                }
+                process::exit(1);
+            }
+        },
+        None => (empty_board(), all_pieces()),
+    };
+
+    println!("position: {}", position::format_position(&board));
 
-fn play(board: Board, pieces: &Vec<Piece>, current_player: &Player, level: usize) -> i64 {
-    if pieces.len() == 0 || level >= MAX_DEPTH {
-        return 0;
+    let current_player = if (16 - pieces.len()) % 2 == 0 {
+        Player::A
+    } else {
+        Player::B
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let candidates = analyze(&board, &pieces, &current_player, &mut rng);
+
+    for candidate in &candidates {
+        println!("{}", candidate);
     }
 
-    // Skip win check if there aren't enough pieces for it to be possible
-    if pieces.len() < 10 && is_win(&board) {
-        match current_player {
-            Player::A => return 1,
-            Player::B => return -1,
+    if let Some(best) = best_candidate(&candidates, &current_player) {
+        println!("best: {}", best);
+    }
+}
+
+/// Options shared by the ways of playing the engine at the terminal
+struct Session<'a> {
+    handicaps: &'a [&'a str],
+    /// Record file to append finished games to
+    export: Option<&'a str>,
+    /// Profile file to rate finished games in
+    rating: Option<&'a str>,
+    /// Explain each of the engine's choices after it makes them
+    explain: bool,
+    /// Time control for each game, if they're timed
+    clock: Option<TimeControl>,
+    seed: u64,
+}
+
+/// Play games from `start` at the terminal. `first` picks who plays as
+/// Player A: human, machine or random.
+fn play_against(
+    opponent_name: &str,
+    first: &str,
+    human: HumanAgent,
+    start: Game,
+    session: &Session,
+) {
+    let human_first = match first {
+        "human" => true,
+        "machine" => false,
+        "random" => StdRng::seed_from_u64(session.seed).gen(),
+        _ => {
+            eprintln!(
+                "Unknown first player {}, expected human, machine or random",
+                first
+            );
+            process::exit(1);
         }
     };
+    let opponent = handicapped_agent_or_exit(opponent_name, session);
+    let human = Box::new(human) as Box<dyn Agent>;
+
+    // Player A always stages first
+    let (mut agents, human_player, names) = if human_first {
+        ([human, opponent], Player::A, ["human", opponent_name])
+    } else {
+        ([opponent, human], Player::B, [opponent_name, "human"])
+    };
 
-    let mut rng = rand::thread_rng();
-    let range = 0..board.len();
-    let mut moves: Vec<(usize, usize, &Piece)> = range
-        .clone()
-        .flat_map(|row_idx| range.clone().map(move |square_idx| (row_idx, square_idx)))
-        .filter(|(row_idx, square_idx)| !board[*row_idx][*square_idx].is_some())
-        .flat_map(|(row_idx, square_idx)| {
-            pieces.iter().map(move |piece| (row_idx, square_idx, piece))
-        })
-        .collect();
-    (&mut moves).shuffle(&mut rng);
-
-    moves
-        .into_par_iter()
-        .take(20)
-        .map(|(row_idx, square_idx, piece)| {
-            let remaining = pieces.clone().into_iter().filter(|p| p != piece).collect();
-            let mut board = board.clone();
-            board[row_idx][square_idx].replace(piece.clone());
-            let path_score = play(board, &remaining, &current_player.toggle(), level + 1);
-
-            if level < 3 {
-                dbg!(level, path_score, row_idx, square_idx);
-            }
+    loop {
+        let mut game = start.clone();
+        println!("You are {}", human_player);
 
-            path_score
-        })
-        .sum()
-}
+        let resolution = play_game(&mut game, &mut agents, human_player, session);
 
-fn empty_board() -> Board {
-    let row = [None, None, None, None];
+        println!("{}", game);
+        println!("{}", resolution);
+        print_forks(&game);
+        finish_game(names, human_player, &game, session);
 
-    [row.clone(), row.clone(), row.clone(), row.clone()]
+        if !agent::confirm("Start a new game?") {
+            break;
+        }
+    }
 }
 
-type Board = [[Option<Piece>; 4]; 4];
+/// Play a best-of-`games` match at the terminal, taking turns with the
+/// engine to stage first and keeping score
+fn play_match(
+    opponent_name: &str,
+    games: &str,
+    human: HumanAgent,
+    rules: Rules,
+    session: &Session,
+) {
+    let games = games.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a number of games, got {}", games);
+        process::exit(1);
+    });
+    let names = ["human", opponent_name];
+    let mut agents = [
+        Box::new(human) as Box<dyn Agent>,
+        handicapped_agent_or_exit(opponent_name, session),
+    ];
+    let mut best_of = Match::new(games, rules);
+
+    loop {
+        // Agents are seated by player, so swap them while the engine is
+        // Player A
+        let swapped = best_of.side(Player::A) == 1;
+        let (human_player, seats) = if swapped {
+            agents.swap(0, 1);
+            (Player::B, [names[1], names[0]])
+        } else {
+            (Player::A, names)
+        };
+        println!("{}", best_of.scoreboard(names));
+        println!("You are {}", human_player);
+
+        let resolution = play_game(best_of.game_mut(), &mut agents, human_player, session);
+        if swapped {
+            agents.swap(0, 1);
+        }
 
-#[derive(Clone, PartialEq)]
-struct Piece {
-    height: Height,
-    color: Color,
-    density: Density,
-    shape: Shape,
+        println!("{}", best_of.game());
+        println!("{}", resolution);
+        print_forks(best_of.game());
+        finish_game(seats, human_player, best_of.game(), session);
+
+        if !best_of.next_game() {
+            break;
+        }
+    }
+
+    println!();
+    println!("{}", best_of.scoreboard(names));
+    match best_of.leader() {
+        Some(side) => println!("{} wins the match", names[side]),
+        None => println!("The match is drawn"),
+    }
 }
 
-#[derive(Clone, PartialEq)]
-enum Height {
-    Tall,
-    Short,
+/// Play `game` out, keeping time if the session is timed. The engine is
+/// told how long it can think each turn, and a move made after the
+/// mover's flag fell is taken back and the game lost on time.
+fn play_game(
+    game: &mut Game,
+    agents: &mut [Box<dyn Agent>; 2],
+    human_player: Player,
+    session: &Session,
+) -> Resolution {
+    let mut clock = session.clock.map(Clock::new);
+    // A status line while the engine thinks, cleared once it has chosen
+    agents[human_player.toggle().index()].on_progress(Box::new(|progress| {
+        if progress.is_done() {
+            eprint!("\r\x1b[K");
+        } else {
+            eprint!("\r{}\x1b[K", progress);
+        }
+    }));
+
+    loop {
+        if let Some(resolution) = game.resolution() {
+            for player in [Player::A, Player::B].iter() {
+                agents[player.index()].game_over(game, *player);
+            }
+            if let Some(standing) = agents[human_player.toggle().index()].standing() {
+                println!("{}", standing);
+            }
+            return resolution;
+        }
+        let player = game
+            .current_player()
+            .expect("a game without a resolution has a player to act");
+
+        if let Some(clock) = &clock {
+            if player == human_player {
+                println!("{}", clock);
+                if clock.is_low(player) {
+                    // The bell character beeps in most terminals
+                    println!("\x07Low on time!");
+                }
+            }
+            agents[player.index()].set_time_budget(clock.budget(player));
+        }
+
+        let before = game.clone();
+        let started = Instant::now();
+        if let Err(err) = game.tick(agents) {
+            eprintln!("Illegal move: {}", err);
+            process::exit(1);
+        }
+        debug!(
+            target: logging::UI,
+            "{} took {:.1}s",
+            player,
+            started.elapsed().as_secs_f64()
+        );
+
+        for threat in agents[player.index()].take_announced_threats() {
+            println!("Engine announces a threat: {}", threat);
+        }
+        if session.explain && player != human_player {
+            if let Some(analysis) = agents[player.index()].analysis() {
+                println!("{}", analysis);
+            }
+        }
+
+        if let Some(clock) = &mut clock {
+            let staged =
+                matches!(game.history().last(), Some((mover, Move::Stage(_))) if *mover == player);
+            if !clock.charge(player, started.elapsed(), staged) {
+                *game = before;
+                game.lose_on_time();
+            }
+        }
+    }
 }
 
-#[derive(Clone, PartialEq)]
-enum Color {
-    Dark,
-    Light,
+/// Point out the moves that set up a win by force
+fn print_forks(game: &Game) {
+    for number in game.fork_moves() {
+        println!("Move {} created an unstoppable fork", number);
+    }
 }
 
-#[derive(Clone, PartialEq)]
-enum Density {
-    Solid,
-    Hollow,
+/// Export and rate a finished game, as the session asks. `names` are the
+/// agents playing A and B.
+fn finish_game(names: [&str; 2], human_player: Player, game: &Game, session: &Session) {
+    if let Some(path) = session.export {
+        export_game(path, names, game);
+    }
+
+    if let Some(path) = session.rating {
+        let opponent_name = names[human_player.toggle().index()];
+        if session.handicaps.is_empty() {
+            rate_game(path, opponent_name, human_player, game);
+        } else {
+            println!("Games with a handicap aren't rated");
+        }
+    }
 }
 
-#[derive(Clone, PartialEq)]
-enum Shape {
-    Round,
-    Square,
+/// Append a finished game to the record file at `path`
+fn export_game(path: &str, names: [&str; 2], game: &Game) {
+    let date = record::today();
+    let variant = record::variant_name(game.rules());
+    let headers = [
+        ("Date", date.as_str()),
+        ("A", names[0]),
+        ("B", names[1]),
+        ("Variant", variant.as_str()),
+    ];
+    if let Err(err) = append_record(path, &record::format_record(&headers, game)) {
+        eprintln!("Couldn't write to {}: {}", path, err);
+    }
 }
 
-enum Player {
-    A,
-    B,
+/// Update the profile at `path` with the result of a finished game against
+/// `opponent_name`
+fn rate_game(path: &str, opponent_name: &str, human_player: Player, game: &Game) {
+    let opponent = match rating::engine_rating(opponent_name) {
+        Some(opponent) => opponent,
+        None => return,
+    };
+    let score = match game.resolution().map(|resolution| resolution.winner()) {
+        Some(Some(winner)) if winner == human_player => 1.0,
+        Some(Some(_)) => 0.0,
+        Some(None) => 0.5,
+        None => return,
+    };
+
+    let mut profile = load_profile(path);
+    let before = profile.rating;
+    profile.record(opponent, score);
+    if let Err(err) = fs::write(path, profile.format()) {
+        eprintln!("Couldn't write to {}: {}", path, err);
+        return;
+    }
+
+    println!(
+        "Rating {:.0} -> {:.0} ({:+.0})",
+        before,
+        profile.rating,
+        profile.rating - before
+    );
 }
 
-impl Player {
-    fn toggle(&self) -> Self {
-        match self {
-            Player::A => Player::B,
-            Player::B => Player::A,
+/// Read the profile at `path`, or start a new one if there's no file yet
+fn load_profile(path: &str) -> Profile {
+    let read = || -> Result<Profile, Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Profile::parse(&text)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Profile::new()),
+            Err(err) => Err(err.into()),
         }
+    };
+
+    read().unwrap_or_else(|err| {
+        eprintln!("Couldn't read {}: {}", path, err);
+        process::exit(1);
+    })
+}
+
+/// Show the profile at `path` and how it should fare against each engine
+fn print_profile(path: &str) {
+    let profile = load_profile(path);
+    println!("{}", profile);
+
+    for (name, engine) in rating::ENGINE_RATINGS {
+        println!(
+            "  vs {} ({:.0}): expect to score {:.0}%",
+            name,
+            engine,
+            rating::expected_score(profile.rating, *engine) * 100.0
+        );
     }
 }
 
-fn is_win(board: &Board) -> bool {
-    for row in 0..board.len() {
-        if winning_row(board, row) {
-            return true;
+/// Set up a position from editor commands, see the editor module docs,
+/// then evaluate it or play on from it against `opponent_name`
+fn edit_position(opponent_name: &str, human: HumanAgent, rules: Rules, session: &Session) {
+    let mut editor = Editor::new();
+    let mut rng = StdRng::seed_from_u64(session.seed);
+    println!("Commands: put <piece> <square>, remove <square>, turn <a|b>,");
+    println!("stage <piece|none>, clear, eval, play <a|b>, quit");
+
+    loop {
+        let game = editor.game(rules);
+        println!();
+        println!("position: {}", editor.position());
+        println!("{}", game);
+        match game.phase() {
+            Phase::AwaitingStage(player) => println!("{} must stage a piece", player),
+            Phase::GameOver(resolution) => println!("{}", resolution),
+            Phase::AwaitingPlacement(..) => {}
         }
-    }
 
-    for col in 0..board.len() {
-        if winning_col(board, col) {
-            return true;
+        let input = match agent::prompt("edit> ") {
+            Some(input) => input,
+            None => return,
+        };
+        let command = match editor::parse_command(&input) {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+        let result = match command {
+            Command::Put(piece, square) => editor.put(piece, square),
+            Command::Remove(square) => editor.remove(square),
+            Command::Turn(player) => {
+                editor.set_turn(player);
+                Ok(())
+            }
+            Command::Stage(piece) => editor.stage(piece),
+            Command::Clear => {
+                editor.clear();
+                Ok(())
+            }
+            Command::Evaluate => {
+                print_evaluation(&game, &mut rng);
+                Ok(())
+            }
+            Command::Play(player) => {
+                if session.export.is_some() || session.rating.is_some() {
+                    println!("Games from an edited position aren't exported or rated");
+                }
+                let first = match player {
+                    Player::A => "human",
+                    Player::B => "machine",
+                };
+                let start = game;
+                let session = Session {
+                    export: None,
+                    rating: None,
+                    ..*session
+                };
+                play_against(opponent_name, first, human, start, &session);
+                return;
+            }
+            Command::Quit => return,
+        };
+
+        if let Err(err) = result {
+            println!("{}", err);
         }
     }
+}
 
-    if left_right_diaganal_win(board) || right_left_diaganal_win(board) {
-        return true;
+/// Print who's winning from random playouts, and the threats they face
+fn print_evaluation(game: &Game, rng: &mut StdRng) {
+    if let Some(resolution) = game.resolution() {
+        println!("{}", resolution);
+        return;
     }
 
-    false
+    let chance = game.evaluate(Player::A, EVAL_PLAYOUTS, rng);
+    println!(
+        "{} wins {:.0}% of random playouts, {} {:.0}%",
+        Player::A,
+        chance * 100.0,
+        Player::B,
+        (1.0 - chance) * 100.0
+    );
+    for threat in game.threats() {
+        println!("Threat: {}", threat);
+    }
 }
 
-fn winning_row(board: &Board, row: usize) -> bool {
-    let column = [
-        board[row][0].as_ref(),
-        board[row][1].as_ref(),
-        board[row][2].as_ref(),
-        board[row][3].as_ref(),
-    ];
+/// Add a game record to the end of the file at `path`, after a blank line
+/// if the file already has records
+fn append_record(path: &str, text: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() > 0 {
+        writeln!(file)?;
+    }
 
-    matching_pieces(&column)
+    file.write_all(text.as_bytes())
 }
 
-fn winning_col(board: &Board, col: usize) -> bool {
-    let column = [
-        board[0][col].as_ref(),
-        board[1][col].as_ref(),
-        board[2][col].as_ref(),
-        board[3][col].as_ref(),
-    ];
-
-    matching_pieces(&column)
+/// Replay and show each game recorded in the file at `path`
+fn import_games(path: &str) {
+    for record in load_records(path) {
+        for (name, value) in &record.headers {
+            println!("{}: {}", name, value);
+        }
+        for entry in record.game.move_log() {
+            println!("{}", entry);
+        }
+        println!();
+        println!("{}", record.game);
+        match record.game.resolution() {
+            Some(resolution) => println!("{}", resolution),
+            None => println!("Unfinished"),
+        }
+        print_forks(&record.game);
+        println!();
+    }
 }
 
-fn left_right_diaganal_win(board: &Board) -> bool {
-    let column = [
-        board[0][0].as_ref(),
-        board[1][1].as_ref(),
-        board[2][2].as_ref(),
-        board[3][3].as_ref(),
-    ];
+/// Read every game in the record file at `path`, exiting if it can't be
+/// read
+fn load_records(path: &str) -> Vec<record::Record> {
+    let read = || -> Result<Vec<record::Record>, Error> {
+        Ok(record::parse_records(&fs::read_to_string(path)?)?)
+    };
 
-    matching_pieces(&column)
+    read().unwrap_or_else(|err| {
+        eprintln!("Couldn't import {}: {}", path, err);
+        process::exit(1);
+    })
 }
 
-fn right_left_diaganal_win(board: &Board) -> bool {
-    let column = [
-        board[0][3].as_ref(),
-        board[1][2].as_ref(),
-        board[2][1].as_ref(),
-        board[3][0].as_ref(),
-    ];
-
-    matching_pieces(&column)
+/// List the games in a record file that match `terms`, one line each,
+/// numbered for `replay`
+fn show_history(path: &str, terms: &[&str]) {
+    let records = load_records(path);
+    let numbers = record::browse(&records, terms).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    for number in numbers {
+        let record = &records[number - 1];
+        let header = |name| record.header(name).unwrap_or("?");
+        println!(
+            "{:>4}. {:<10}  {} vs {}  {:<8} {:<7} {} moves",
+            number,
+            header("Date"),
+            header("A"),
+            header("B"),
+            record.header("Variant").unwrap_or("standard"),
+            record.result(),
+            record.game.history().len()
+        );
+    }
 }
 
-fn matching_pieces(pieces: &[Option<&Piece>; 4]) -> bool {
-    match &pieces[0] {
-        Some(first_piece) => {
-            let mut color = Some(&first_piece.color);
-            let mut shape = Some(&first_piece.shape);
-            let mut density = Some(&first_piece.density);
-            let mut height = Some(&first_piece.height);
-            for piece in pieces.iter().skip(1) {
-                match piece {
-                    Some(piece) => {
-                        if color != Some(&piece.color) {
-                            color.take();
-                        }
-                        if height != Some(&piece.height) {
-                            height.take();
-                        }
-                        if shape != Some(&piece.shape) {
-                            shape.take();
-                        }
-                        if density != Some(&piece.density) {
-                            density.take();
-                        }
-                    }
-                    None => return false,
-                }
+/// Step through a recorded game one move at a time. Before any move the
+/// player can branch off and play a line of their own against
+/// `opponent_name`, coming back to the recorded game afterwards.
+fn replay_game(
+    path: &str,
+    number: &str,
+    opponent_name: &str,
+    human: HumanAgent,
+    session: &Session,
+) {
+    let records = load_records(path);
+    let record = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| records.get(number.checked_sub(1)?))
+        .unwrap_or_else(|| {
+            eprintln!("Expected a game number from 1 to {}", records.len());
+            process::exit(1);
+        });
 
-                if color.is_none() && shape.is_none() && density.is_none() && height.is_none() {
-                    return false;
-                }
+    let mut game = Game::with_rules(record.game.rules());
+    println!("{}", game);
+    for (player, mv) in record.game.history() {
+        loop {
+            match agent::prompt("Press enter for the next move, or branch to play on from here: ") {
+                Some(input) if input.trim() == "branch" => {}
+                Some(_) => break,
+                None => return,
             }
-            return true;
+
+            play_branch(&record.game, &game, opponent_name, &human, session);
+            println!("Back to the recorded game");
+            println!("{}", game);
         }
-        None => return false,
+        game.apply(mv.clone())
+            .expect("recorded moves were checked when read");
+        println!("{} plays {}", player, mv);
+        println!("{}", game);
+    }
+
+    match record.game.resolution() {
+        Some(resolution) => println!("{}", resolution),
+        None => println!("Unfinished"),
     }
+    print_forks(&record.game);
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn tall_win_test() {
-        let mut board = empty_board();
-        board[0][0] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Solid,
-            shape: Round,
-        });
-        board[0][1] = Some(Piece {
-            height: Tall,
-            color: Light,
-            density: Solid,
-            shape: Round,
-        });
-        board[0][2] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Hollow,
-            shape: Round,
-        });
-        board[0][3] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Solid,
-            shape: Square,
-        });
+/// Play on from `position`, part way through `original`, as whoever is to
+/// act there, then list the moves where the branch parted from the
+/// original
+fn play_branch(
+    original: &Game,
+    position: &Game,
+    opponent_name: &str,
+    human: &HumanAgent,
+    session: &Session,
+) {
+    let human_player = position
+        .current_player()
+        .expect("a recorded move is still to come");
+    let opponent = handicapped_agent_or_exit(opponent_name, session);
+    let human = Box::new(human.clone()) as Box<dyn Agent>;
+    let mut agents = match human_player {
+        Player::A => [human, opponent],
+        Player::B => [opponent, human],
+    };
 
-        assert_eq!(is_win(&board), true);
+    let mut branch = position.clone();
+    println!("You are {}", human_player);
+    let resolution = play_game(&mut branch, &mut agents, human_player, session);
+    println!("{}", branch);
+    println!("{}", resolution);
+
+    println!("Variation:");
+    for entry in original.variation(&branch) {
+        println!("  {}", entry);
     }
+}
 
-    #[test]
-    fn dark_win_test() {
-        let mut board = empty_board();
-        board[0][0] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Solid,
-            shape: Round,
-        });
-        board[1][0] = Some(Piece {
-            height: Short,
-            color: Dark,
-            density: Solid,
-            shape: Round,
-        });
-        board[2][0] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Hollow,
-            shape: Round,
-        });
-        board[3][0] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Solid,
-            shape: Square,
-        });
+/// Explain the game, listing the pieces and lines from the engine's own
+/// definitions
+fn print_rules() {
+    println!("Quarto is played on a 4x4 board with 16 different pieces. Each turn, you");
+    println!("place the piece your opponent chose for you, then choose one of the");
+    println!("remaining pieces for them to place. Whoever completes a line of four");
+    println!("pieces that share an attribute wins. If the board fills up first, it's a");
+    println!("draw.");
+    println!();
+
+    println!("Every piece has four attributes:");
+    println!("  {}: {} or {}", Attribute::Height, Tall, Short);
+    println!("  {}: {} or {}", Attribute::Color, Dark, Light);
+    println!("  {}: {} or {}", Attribute::Density, Solid, Hollow);
+    println!("  {}: {} or {}", Attribute::Shape, Square, Round);
+    println!();
+
+    println!("The pieces, by the digit used for them on the board:");
+    for piece in all_pieces() {
+        println!("  {:x}: {}", piece.index(), piece);
+    }
+    println!();
+
+    println!("A line can be a row, a column, or one of the two diagonals:");
+    let examples = [
+        Line::Row(0),
+        Line::Column(0),
+        Line::Diagonal,
+        Line::AntiDiagonal,
+    ];
+    print_line_diagrams(&examples);
+    println!();
+
+    println!("With --torus the board wraps around its edges, adding broken diagonals:");
+    print_line_diagrams(&[Line::WrappingDiagonal(1), Line::WrappingAntiDiagonal(2)]);
+}
 
-        assert_eq!(is_win(&board), true);
+/// Draw each line's squares on its own small board, side by side under
+/// the kind of line
+fn print_line_diagrams(examples: &[Line]) {
+    let width = examples
+        .iter()
+        .map(|example| example.kind().len() + 2)
+        .max()
+        .unwrap_or(0)
+        .max(16);
+    let headings: Vec<String> = examples
+        .iter()
+        .map(|example| format!("{:<width$}", example.kind(), width = width))
+        .collect();
+    println!("  {}", headings.concat().trim_end());
+    for row_idx in 0..4 {
+        let diagrams: Vec<String> = examples
+            .iter()
+            .map(|example| {
+                let squares: Vec<&str> = (0..4)
+                    .map(|square_idx| {
+                        if example.squares().contains(&(row_idx, square_idx)) {
+                            "#"
+                        } else {
+                            "."
+                        }
+                    })
+                    .collect();
+                format!("{:<width$}", squares.join(" "), width = width)
+            })
+            .collect();
+        println!("  {}", diagrams.concat().trim_end());
     }
+}
 
-    #[test]
-    fn diagonal_win_test() {
-        let mut board = empty_board();
-        board[0][0] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Solid,
-            shape: Round,
-        });
-        board[1][1] = Some(Piece {
-            height: Short,
-            color: Dark,
-            density: Solid,
-            shape: Round,
-        });
-        board[2][2] = Some(Piece {
-            height: Tall,
-            color: Dark,
-            density: Hollow,
-            shape: Round,
-        });
-        board[3][3] = Some(Piece {
-            height: Tall,
-            color: Light,
-            density: Solid,
-            shape: Round,
-        });
+/// Pit two engine agents against each other and report the results
+fn run_tournament(first: &str, second: &str, games: &str, seed: u64) {
+    let games = games.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a number of games, got {}", games);
+        process::exit(1);
+    });
+    let mut agents = [
+        engine_agent_or_exit(first, seed),
+        engine_agent_or_exit(second, seed.wrapping_add(1)),
+    ];
+
+    let report = tournament::run(&mut agents, games).unwrap_or_else(|err| {
+        eprintln!("Illegal move: {}", err);
+        process::exit(1);
+    });
+    println!("{} vs {}", first, second);
+    println!("{}", report);
+}
 
-        assert_eq!(is_win(&board), true);
+/// Count move sequences up to `depth` half-turns from a position in the
+/// notation of the `position` module, or from the start, with Player A to
+/// stage
+fn run_perft(depth: &str, position: Option<&str>, rules: Rules) {
+    let depth: usize = depth.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a depth, got {}", depth);
+        process::exit(1);
+    });
+    let editor = match position {
+        Some(position) => Editor::from_position(position).unwrap_or_else(|err| {
+            eprintln!("Couldn't read position: {}", err);
+            process::exit(1);
+        }),
+        None => Editor::new(),
+    };
+    let game = editor.game(rules);
+
+    for depth in 1..=depth {
+        let started = Instant::now();
+        let count = game.perft(depth);
+        println!("perft {}: {} ({:?})", depth, count, started.elapsed());
     }
+}
 
-    #[test]
-    fn empty_is_not_win_test() {
-        let board = empty_board();
+/// Play engine agents against each other in fixed seats and report on the
+/// games, writing each one to `out` as JSON if it ends in .json, or as CSV
+fn run_simulation(
+    first: &str,
+    second: &str,
+    games: &str,
+    out: Option<&str>,
+    rules: Rules,
+    seed: u64,
+) {
+    let games = games.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a number of games, got {}", games);
+        process::exit(1);
+    });
+    // Check the names up front rather than in the middle of the games
+    engine_agent_or_exit(first, seed);
+    engine_agent_or_exit(second, seed);
+
+    let names = [first, second];
+    let simulation = tournament::simulate(
+        |player, seed| engine_agent_or_exit(names[player.index()], seed),
+        games,
+        seed,
+        rules,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("Illegal move: {}", err);
+        process::exit(1);
+    });
+    println!("{} as Player A vs {} as Player B", first, second);
+    println!("{}", simulation);
+
+    if let Some(path) = out {
+        let text = if path.ends_with(".json") {
+            simulation.to_json()
+        } else {
+            simulation.to_csv()
+        };
+        if let Err(err) = fs::write(path, text) {
+            eprintln!("Couldn't write to {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
 
-        assert_eq!(is_win(&board), false);
+fn engine_agent_or_exit(name: &str, seed: u64) -> Box<dyn Agent> {
+    if name == "adaptive" {
+        return Box::new(AdaptiveAgent::new(seed));
     }
+
+    agent::engine_agent(name, seed).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown agent {}, expected adaptive or one of {}",
+            name,
+            agent::ENGINE_AGENTS.join(", ")
+        );
+        process::exit(1);
+    })
+}
+
+/// An engine agent held back by the session's `--handicap` options
+fn handicapped_agent_or_exit(name: &str, session: &Session) -> Box<dyn Agent> {
+    Box::new(HandicappedAgent {
+        inner: engine_agent_or_exit(name, session.seed),
+        announce_threats: session.handicaps.contains(&"threats"),
+        random_first_stage: session.handicaps.contains(&"first-stage"),
+        rng: StdRng::seed_from_u64(session.seed.wrapping_add(1)),
+        announced: Vec::new(),
+    })
 }
@@ -0,0 +1,154 @@
+//! Text notation for board positions.
+//!
+//! A position is four rows separated by `/`, each with four squares. An
+//! empty square is `.` and an occupied one is the hex digit of the piece's
+//! index, so `0.../..../..../...f` has the tall dark solid square piece in
+//! one corner and the short light hollow round piece in the opposite one.
+
+use std::fmt;
+
+use crate::{empty_board, Board, Piece};
+
+#[derive(Debug, PartialEq)]
+pub enum PositionError {
+    RowCount(usize),
+    RowLength(usize),
+    UnknownPiece(char),
+    DuplicatePiece(char),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::RowCount(count) => write!(f, "expected 4 rows, found {}", count),
+            PositionError::RowLength(row) => write!(f, "row {} does not have 4 squares", row + 1),
+            PositionError::UnknownPiece(c) => write!(f, "unknown piece '{}'", c),
+            PositionError::DuplicatePiece(c) => write!(f, "piece '{}' appears more than once", c),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+pub fn parse_position(position: &str) -> Result<Board, PositionError> {
+    let rows: Vec<&str> = position.trim().split('/').collect();
+    let mut board = empty_board();
+
+    if rows.len() != board.len() {
+        return Err(PositionError::RowCount(rows.len()));
+    }
+
+    let mut seen = [false; 16];
+
+    for (row_idx, row) in rows.iter().enumerate() {
+        if row.chars().count() != board.len() {
+            return Err(PositionError::RowLength(row_idx));
+        }
+
+        for (square_idx, c) in row.chars().enumerate() {
+            if c == '.' {
+                continue;
+            }
+
+            let piece = c
+                .to_digit(16)
+                .and_then(|index| Piece::from_index(index as u8))
+                .ok_or(PositionError::UnknownPiece(c))?;
+
+            let index = piece.index() as usize;
+            if seen[index] {
+                return Err(PositionError::DuplicatePiece(c));
+            }
+            seen[index] = true;
+
+            board[row_idx][square_idx] = Some(piece);
+        }
+    }
+
+    Ok(board)
+}
+
+pub fn format_position(board: &Board) -> String {
+    board
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|square| match square {
+                    Some(piece) => format!("{:x}", piece.index()),
+                    None => ".".to_string(),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub struct Scenario {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub position: &'static str,
+}
+
+pub const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "three-tall",
+        description: "Three tall solid pieces share the top row, find a piece that is safe to give",
+        position: "014./..../..../....",
+    },
+    Scenario {
+        name: "crossfire",
+        description: "The first column is one solid piece from a quarto, find the save",
+        position: "03../5a../..../c...",
+    },
+    Scenario {
+        name: "crowded",
+        description: "Late game with five squares left, find a placement that survives",
+        position: "0f69/a.5./3c.e/.2b.",
+    },
+];
+
+pub fn scenario(name: &str) -> Option<&'static Scenario> {
+    SCENARIOS.iter().find(|scenario| scenario.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::is_win;
+
+    #[test]
+    fn round_trip_test() {
+        let position = "0.../.5../..a./...f";
+        let board = parse_position(position).unwrap();
+
+        assert_eq!(board[1][1].as_ref().map(Piece::index), Some(5));
+        assert_eq!(format_position(&board), position);
+    }
+
+    #[test]
+    fn invalid_position_test() {
+        assert_eq!(parse_position("..../...."), Err(PositionError::RowCount(2)));
+        assert_eq!(
+            parse_position("..../.../..../...."),
+            Err(PositionError::RowLength(1))
+        );
+        assert_eq!(
+            parse_position("..x./..../..../...."),
+            Err(PositionError::UnknownPiece('x'))
+        );
+        assert_eq!(
+            parse_position("1.../..../..1./...."),
+            Err(PositionError::DuplicatePiece('1'))
+        );
+    }
+
+    #[test]
+    fn scenarios_parse_test() {
+        for scenario in SCENARIOS {
+            let board = parse_position(scenario.position)
+                .unwrap_or_else(|err| panic!("{} does not parse: {}", scenario.name, err));
+
+            assert!(!is_win(&board), "{} is already won", scenario.name);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Elo ratings for a person playing the engine agents, which each have a
+//! fixed rating of their own.
+//!
+//! A profile is kept as a small text file of `key value` lines:
+//!
+//! ```text
+//! rating 1216.0
+//! wins 1
+//! losses 0
+//! draws 0
+//! ```
+
+use std::fmt;
+
+/// Rating of a new profile
+pub const INITIAL_RATING: f64 = 1200.0;
+
+/// Fixed ratings of the engine agents
+pub const ENGINE_RATINGS: &[(&str, f64)] = &[
+    ("random", 800.0),
+    ("montecarlo", 1400.0),
+    ("minimax", 1500.0),
+];
+
+/// Most a rating can move in one game
+const K_FACTOR: f64 = 32.0;
+
+/// The fixed rating of the engine agent called `name`
+pub fn engine_rating(name: &str) -> Option<f64> {
+    ENGINE_RATINGS
+        .iter()
+        .find(|(agent, _)| *agent == name)
+        .map(|(_, rating)| *rating)
+}
+
+/// The score a player rated `rating` is expected to average against one
+/// rated `opponent`, counting draws as half a win
+pub fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RatingError {
+    BadLine(String),
+    Missing(&'static str),
+}
+
+impl fmt::Display for RatingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RatingError::BadLine(line) => write!(f, "can't read {}", line),
+            RatingError::Missing(key) => write!(f, "no {} given", key),
+        }
+    }
+}
+
+impl std::error::Error for RatingError {}
+
+/// A person's rating and the games behind it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub rating: f64,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile {
+            rating: INITIAL_RATING,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+        }
+    }
+
+    pub fn games(&self) -> usize {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Count a game with `score` (1 for a win, 0.5 for a draw, 0 for a
+    /// loss) against an opponent rated `opponent`, returning the change in
+    /// rating
+    pub fn record(&mut self, opponent: f64, score: f64) -> f64 {
+        if score > 0.5 {
+            self.wins += 1;
+        } else if score < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+
+        let change = K_FACTOR * (score - expected_score(self.rating, opponent));
+        self.rating += change;
+        change
+    }
+
+    /// Read a profile in the format of the module docs
+    pub fn parse(text: &str) -> Result<Self, RatingError> {
+        let mut rating = None;
+        let mut counts = [None; 3];
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let bad_line = || RatingError::BadLine(line.to_string());
+            let (key, value) = line.split_once(' ').ok_or_else(bad_line)?;
+            let value = value.trim();
+
+            match key {
+                "rating" => rating = Some(value.parse().map_err(|_| bad_line())?),
+                "wins" => counts[0] = Some(value.parse().map_err(|_| bad_line())?),
+                "losses" => counts[1] = Some(value.parse().map_err(|_| bad_line())?),
+                "draws" => counts[2] = Some(value.parse().map_err(|_| bad_line())?),
+                _ => return Err(bad_line()),
+            }
+        }
+
+        Ok(Profile {
+            rating: rating.ok_or(RatingError::Missing("rating"))?,
+            wins: counts[0].ok_or(RatingError::Missing("wins"))?,
+            losses: counts[1].ok_or(RatingError::Missing("losses"))?,
+            draws: counts[2].ok_or(RatingError::Missing("draws"))?,
+        })
+    }
+
+    /// Write the profile out in the format of the module docs
+    pub fn format(&self) -> String {
+        format!(
+            "rating {:.1}\nwins {}\nlosses {}\ndraws {}\n",
+            self.rating, self.wins, self.losses, self.draws
+        )
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::new()
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Rating {:.0} after {} games: {} wins, {} losses, {} draws",
+            self.rating,
+            self.games(),
+            self.wins,
+            self.losses,
+            self.draws
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_score_test() {
+        assert_eq!(expected_score(1500.0, 1500.0), 0.5);
+        assert!((expected_score(1600.0, 1200.0) - 0.909).abs() < 0.001);
+        assert!(
+            (expected_score(1200.0, 1600.0) + expected_score(1600.0, 1200.0) - 1.0).abs() < 1e-9
+        );
+        assert_eq!(engine_rating("human"), None);
+        for name in crate::agent::ENGINE_AGENTS {
+            assert!(engine_rating(name).is_some(), "{} has no rating", name);
+        }
+    }
+
+    #[test]
+    fn record_test() {
+        let mut profile = Profile::new();
+
+        assert_eq!(profile.record(1200.0, 1.0), 16.0);
+        assert_eq!(profile.record(profile.rating, 0.5), 0.0);
+        assert!(profile.record(800.0, 0.0) < -K_FACTOR * 0.9);
+        assert_eq!((profile.wins, profile.losses, profile.draws), (1, 1, 1));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut profile = Profile::new();
+        profile.record(1400.0, 1.0);
+
+        let text = profile.format();
+        assert_eq!(text, "rating 1224.3\nwins 1\nlosses 0\ndraws 0\n");
+        let parsed = Profile::parse(&text).unwrap();
+        assert_eq!(parsed.wins, 1);
+        assert!((parsed.rating - profile.rating).abs() < 0.1);
+
+        assert_eq!(
+            Profile::parse("rating high"),
+            Err(RatingError::BadLine("rating high".to_string()))
+        );
+        assert_eq!(
+            Profile::parse("rating 1200"),
+            Err(RatingError::Missing("wins"))
+        );
+    }
+}
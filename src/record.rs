@@ -0,0 +1,473 @@
+//! Records of whole games in a PGN-like format: bracketed headers, a blank
+//! line, then the moves numbered by staged piece and ending with the
+//! result.
+//!
+//! ```text
+//! [Date "2026-10-16"]
+//! [A "human"]
+//! [B "montecarlo"]
+//! [Variant "standard"]
+//! [Result "0-1"]
+//!
+//! 1. 5 b3 2. a c1 3. 7 0-1
+//! ```
+//!
+//! Moves use the notation of `Game::parse_move`. The result is `1-0` or
+//! `0-1` when Player A or B wins, `1/2-1/2` for a draw and `*` for a game
+//! still in progress. A win the moves don't reach was a resignation.
+//! Records always start from an empty board, and the Variant header, if
+//! there is one, names the rules: `standard`, or any of `no-adjacent`,
+//! `call-quarto` and `torus` joined with commas. Under `call-quarto` a win whose
+//! quarto is waiting to be called was a call by the winner.
+
+use std::fmt;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::game::{Game, Move, MoveError, Resolution, Rules};
+use crate::Player;
+
+/// A game and the headers it was recorded with, other than the result
+pub struct Record {
+    pub headers: Vec<(String, String)>,
+    pub game: Game,
+}
+
+impl Record {
+    /// The value of the header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The result token written after the moves
+    pub fn result(&self) -> &'static str {
+        result_token(&self.game)
+    }
+
+    /// Whether the record has `value` under `key`, which names a header or
+    /// is `result`, or `player` for either of the A and B headers
+    fn matches(&self, key: &str, value: &str) -> bool {
+        match key {
+            "result" => self.result() == value,
+            "player" => self.header("A") == Some(value) || self.header("B") == Some(value),
+            _ => self.header(key) == Some(value),
+        }
+    }
+}
+
+/// Pick records out of a file, returning their numbers, counting from 1.
+/// Each term is either `<key>=<value>`, keeping the records `Record::matches`
+/// accepts, or `sort=<key>`, ordering them by a header or by `moves`, most
+/// first with `sort=-<key>`. Records keep their order in the file
+/// otherwise.
+pub fn browse(records: &[Record], terms: &[&str]) -> Result<Vec<usize>, String> {
+    let mut numbers: Vec<usize> = (1..=records.len()).collect();
+    let mut sort = None;
+
+    for term in terms {
+        let (key, value) = term
+            .split_once('=')
+            .ok_or(format!("expected key=value, got {}", term))?;
+        if key == "sort" {
+            sort = Some(value);
+        } else {
+            numbers.retain(|number| records[number - 1].matches(key, value));
+        }
+    }
+
+    if let Some(key) = sort {
+        let (key, descending) = match key.strip_prefix('-') {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+        if key == "moves" {
+            numbers.sort_by_key(|number| records[number - 1].game.history().len());
+        } else {
+            numbers.sort_by_key(|number| records[number - 1].header(key).unwrap_or(""));
+        }
+        if descending {
+            numbers.reverse();
+        }
+    }
+
+    Ok(numbers)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecordError {
+    BadHeader(String),
+    UnknownVariant(String),
+    BadMove(String),
+    IllegalMove(String, MoveError),
+    /// The moves ran out before a result token
+    MissingResult,
+    /// The result token doesn't fit the moves
+    WrongResult(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::BadHeader(line) => write!(f, "can't read header {}", line),
+            RecordError::UnknownVariant(name) => write!(f, "unknown variant {}", name),
+            RecordError::BadMove(token) => write!(f, "can't read move {}", token),
+            RecordError::IllegalMove(token, err) => write!(f, "move {}: {}", token, err),
+            RecordError::MissingResult => write!(f, "the moves don't end with a result"),
+            RecordError::WrongResult(token) => {
+                write!(f, "result {} doesn't match the moves", token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecordError::IllegalMove(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Write `game` out with `headers` followed by its result
+pub fn format_record(headers: &[(&str, &str)], game: &Game) -> String {
+    let result = result_token(game);
+    let mut text = String::new();
+
+    for (name, value) in headers {
+        text.push_str(&format!("[{} \"{}\"]\n", name, value));
+    }
+    text.push_str(&format!("[Result \"{}\"]\n\n", result));
+
+    let mut staged = 0;
+    for (_, mv) in game.history() {
+        if let Move::Stage(_) = mv {
+            staged += 1;
+            text.push_str(&format!("{}. ", staged));
+        }
+        text.push_str(&format!("{} ", mv));
+    }
+    text.push_str(result);
+    text.push('\n');
+
+    text
+}
+
+fn result_token(game: &Game) -> &'static str {
+    match game.resolution().map(|resolution| resolution.winner()) {
+        Some(Some(Player::A)) => "1-0",
+        Some(Some(Player::B)) => "0-1",
+        Some(None) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+/// Read every record in `text`, replaying the moves to check them
+pub fn parse_records(text: &str) -> Result<Vec<Record>, RecordError> {
+    let mut records = Vec::new();
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut game = Game::new();
+    let mut in_moves = false;
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') && !in_moves {
+            let (name, value) = parse_header(line)?;
+            if name == "Variant" {
+                let rules = variant_rules(&value)
+                    .ok_or_else(|| RecordError::UnknownVariant(value.clone()))?;
+                game = Game::with_rules(rules);
+            }
+            headers.push((name, value));
+            continue;
+        }
+
+        for token in line.split_whitespace() {
+            in_moves = true;
+
+            // Move numbers are only there for people reading along
+            if token.ends_with('.') && token[..token.len() - 1].parse::<usize>().is_ok() {
+                continue;
+            }
+
+            if let Some(outcome) = parse_result(token) {
+                finish(&mut game, outcome, token)?;
+                headers.retain(|(name, _)| name != "Result");
+                records.push(Record {
+                    headers: mem::take(&mut headers),
+                    game: mem::take(&mut game),
+                });
+                in_moves = false;
+                continue;
+            }
+
+            let mv = game
+                .parse_move(token)
+                .ok_or_else(|| RecordError::BadMove(token.to_string()))?;
+            game.apply(mv)
+                .map_err(|err| RecordError::IllegalMove(token.to_string(), err))?;
+        }
+    }
+
+    if in_moves || !headers.is_empty() {
+        return Err(RecordError::MissingResult);
+    }
+
+    Ok(records)
+}
+
+fn parse_header(line: &str) -> Result<(String, String), RecordError> {
+    let bad_header = || RecordError::BadHeader(line.to_string());
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .ok_or_else(bad_header)?;
+    let (name, value) = inner.split_once(' ').ok_or_else(bad_header)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(bad_header)?;
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Name for the Variant header of a game played under `rules`: the
+/// optional rules in use joined with commas, or `standard`
+pub fn variant_name(rules: Rules) -> String {
+    let mut names = Vec::new();
+    if rules.no_adjacent_repeat {
+        names.push("no-adjacent");
+    }
+    if rules.call_quarto {
+        names.push("call-quarto");
+    }
+    if rules.torus {
+        names.push("torus");
+    }
+
+    if names.is_empty() {
+        "standard".to_string()
+    } else {
+        names.join(",")
+    }
+}
+
+fn variant_rules(name: &str) -> Option<Rules> {
+    let mut rules = Rules::default();
+
+    for part in name.split(',') {
+        match part.trim() {
+            "standard" => {}
+            "no-adjacent" => rules.no_adjacent_repeat = true,
+            "call-quarto" => rules.call_quarto = true,
+            "torus" => rules.torus = true,
+            _ => return None,
+        }
+    }
+
+    Some(rules)
+}
+
+/// What a result token says about how the game went
+enum Outcome {
+    Winner(Player),
+    Draw,
+    Unfinished,
+}
+
+fn parse_result(token: &str) -> Option<Outcome> {
+    match token {
+        "1-0" => Some(Outcome::Winner(Player::A)),
+        "0-1" => Some(Outcome::Winner(Player::B)),
+        "1/2-1/2" => Some(Outcome::Draw),
+        "*" => Some(Outcome::Unfinished),
+        _ => None,
+    }
+}
+
+/// Check the game ended the way the result token says, resigning for the
+/// loser if the moves stop short of a win
+fn finish(game: &mut Game, outcome: Outcome, token: &str) -> Result<(), RecordError> {
+    let resolution = game.resolution();
+
+    match (outcome, resolution) {
+        (Outcome::Winner(player), Some(resolution)) if resolution.winner() == Some(player) => {
+            Ok(())
+        }
+        (Outcome::Draw, Some(Resolution::Draw)) => Ok(()),
+        (Outcome::Unfinished, None) => Ok(()),
+        // Whoever called an uncalled quarto won it
+        (Outcome::Winner(player), None)
+            if game.uncalled_quarto().is_some() && game.current_player() == Some(player) =>
+        {
+            game.call_quarto()
+                .map_err(|_| RecordError::WrongResult(token.to_string()))
+        }
+        (Outcome::Winner(player), None) if game.current_player() == Some(player.toggle()) => {
+            game.resign();
+            Ok(())
+        }
+        _ => Err(RecordError::WrongResult(token.to_string())),
+    }
+}
+
+/// Today's date as YYYY-MM-DD, in UTC
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or(0);
+
+    format_date(days as i64)
+}
+
+/// The date `days` after 1970-01-01, counting in 400-year eras that start
+/// on March 1st so leap days fall at the end of each year
+fn format_date(days: i64) -> String {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Piece;
+
+    fn played_game() -> Game {
+        let mut game = Game::new();
+        for mv in "5 b3 a c1 7".split_whitespace() {
+            let mv = game.parse_move(mv).unwrap();
+            game.apply(mv).unwrap();
+        }
+
+        game
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut game = played_game();
+        game.resign();
+        let text = format_record(&[("A", "human"), ("B", "montecarlo")], &game);
+
+        assert_eq!(
+            text,
+            "[A \"human\"]\n[B \"montecarlo\"]\n[Result \"1-0\"]\n\n1. 5 b3 2. a c1 3. 7 1-0\n"
+        );
+
+        let records = parse_records(&format!("{}\n{}", text, text)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].headers,
+            [
+                ("A".to_string(), "human".to_string()),
+                ("B".to_string(), "montecarlo".to_string()),
+            ]
+        );
+        assert_eq!(records[1].game.board(), game.board());
+        assert_eq!(records[1].game.history(), game.history());
+        assert_eq!(records[1].game.resolution(), game.resolution());
+    }
+
+    #[test]
+    fn invalid_records_test() {
+        let bad = |text: &str| parse_records(text).err().unwrap();
+
+        assert_eq!(
+            bad("[Date 2026]\n\n*"),
+            RecordError::BadHeader("[Date 2026]".to_string())
+        );
+        assert_eq!(bad("1. 5 z9 *"), RecordError::BadMove("z9".to_string()));
+        assert_eq!(
+            bad("[Variant \"giant\"]\n\n*"),
+            RecordError::UnknownVariant("giant".to_string())
+        );
+        assert_eq!(
+            bad("[Variant \"no-adjacent\"]\n\n1. 5 b3 2. 1 b2 *"),
+            RecordError::IllegalMove("b2".to_string(), MoveError::AdjacentToLast((1, 1)))
+        );
+        assert_eq!(
+            bad("1. 5 b3 2. 5 *"),
+            RecordError::IllegalMove(
+                "5".to_string(),
+                MoveError::Unavailable(Piece::from_index(5).unwrap())
+            )
+        );
+        assert_eq!(bad("1. 5 b3"), RecordError::MissingResult);
+        assert_eq!(
+            bad("1. 5 b3 1/2-1/2"),
+            RecordError::WrongResult("1/2-1/2".to_string())
+        );
+        // Player B is to stage, so only they could have resigned
+        assert_eq!(
+            bad("1. 5 b3 0-1"),
+            RecordError::WrongResult("0-1".to_string())
+        );
+    }
+
+    #[test]
+    fn browse_test() {
+        let text = "[Date \"2024-03-01\"]\n[A \"human\"]\n[B \"minimax\"]\n\n1. 5 b3 1-0\n\
+                    [Date \"2024-01-15\"]\n[A \"montecarlo\"]\n[B \"human\"]\n\n1. 5 b3 2. a c1 *\n\
+                    [Date \"2024-02-09\"]\n[A \"human\"]\n[B \"montecarlo\"]\n\n0-1\n";
+        let records = parse_records(text).unwrap();
+
+        assert_eq!(browse(&records, &[]), Ok(vec![1, 2, 3]));
+        assert_eq!(browse(&records, &["sort=date"]), Ok(vec![2, 3, 1]));
+        assert_eq!(browse(&records, &["sort=-moves"]), Ok(vec![2, 1, 3]));
+        assert_eq!(browse(&records, &["player=montecarlo"]), Ok(vec![2, 3]));
+        assert_eq!(browse(&records, &["A=human", "result=1-0"]), Ok(vec![1]));
+        assert_eq!(records[0].header("date"), Some("2024-03-01"));
+        assert_eq!(
+            browse(&records, &["human"]),
+            Err("expected key=value, got human".to_string())
+        );
+    }
+
+    #[test]
+    fn call_quarto_test() {
+        let call = "[Variant \"call-quarto\"]\n\n1. 0 a1 2. 1 b1 3. 4 c1 4. 5 d1 1-0";
+        let claim = "[Variant \"call-quarto\"]\n\n1. 0 a1 2. 1 b1 3. 4 c1 4. 5 d1 5. 2 0-1";
+        let missed = "[Variant \"call-quarto\"]\n\n1. 0 a1 2. 1 b1 3. 4 c1 4. 5 d1 5. 2 a2 *";
+
+        let records = parse_records(&format!("{}\n{}\n{}", call, claim, missed)).unwrap();
+        let winners: Vec<_> = records
+            .iter()
+            .map(|record| record.game.resolution().and_then(|r| r.winner()))
+            .collect();
+        assert_eq!(winners, [Some(Player::A), Some(Player::B), None]);
+
+        let rules = records[0].game.rules();
+        assert_eq!(variant_name(rules), "call-quarto");
+        assert_eq!(
+            variant_rules("torus,no-adjacent,call-quarto")
+                .map(variant_name)
+                .as_deref(),
+            Some("no-adjacent,call-quarto,torus")
+        );
+        assert_eq!(variant_rules("call-quarto,giant"), None);
+    }
+
+    #[test]
+    fn format_date_test() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11_016), "2000-02-29");
+        assert_eq!(format_date(20_742), "2026-10-16");
+    }
+}
@@ -0,0 +1,513 @@
+//! Matches between engine agents, for measuring whether a change to the
+//! search actually makes it stronger, simulations of many games for
+//! statistics about the game itself, and best-of-N matches that keep score
+//! for a person playing the engine.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::agent::Agent;
+use crate::game::{Game, Move, MoveError, Rules};
+use crate::{Estimate, Player};
+
+/// Results from the point of view of the first agent
+pub struct Report {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    /// 1 for a win, 0.5 for a draw and 0 for a loss, per game
+    scores: Vec<f64>,
+    think_time: [Duration; 2],
+    moves: [u32; 2],
+}
+
+/// Play `games` games, swapping which agent stages first each game. Fails
+/// with the first move an agent makes that the rules don't allow.
+pub fn run(agents: &mut [Box<dyn Agent>; 2], games: usize) -> Result<Report, MoveError> {
+    let mut report = Report {
+        wins: 0,
+        losses: 0,
+        draws: 0,
+        scores: Vec::with_capacity(games),
+        think_time: [Duration::default(); 2],
+        moves: [0; 2],
+    };
+
+    for game_idx in 0..games {
+        // Agents are seated by player, so swap them when the first agent
+        // is playing B
+        let first_seat = if game_idx % 2 == 0 {
+            Player::A
+        } else {
+            agents.swap(0, 1);
+            Player::B
+        };
+        let mut game = Game::new();
+
+        let resolution = loop {
+            if let Some(resolution) = game.resolution() {
+                break resolution;
+            }
+
+            let agent_idx = if game.current_player() == Some(first_seat) {
+                0
+            } else {
+                1
+            };
+            let started = Instant::now();
+            if let Err(err) = game.tick(agents) {
+                // Seat the agents back the way they came
+                if first_seat == Player::B {
+                    agents.swap(0, 1);
+                }
+                return Err(err);
+            }
+            report.think_time[agent_idx] += started.elapsed();
+            report.moves[agent_idx] += 1;
+        };
+
+        if first_seat == Player::B {
+            agents.swap(0, 1);
+        }
+
+        match resolution.winner() {
+            Some(player) if player == first_seat => {
+                report.wins += 1;
+                report.scores.push(1.0);
+            }
+            Some(_) => {
+                report.losses += 1;
+                report.scores.push(0.0);
+            }
+            None => {
+                report.draws += 1;
+                report.scores.push(0.5);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// How one simulated game went
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedGame {
+    /// Seed the agents for this game were built with
+    pub seed: u64,
+    pub winner: Option<Player>,
+    /// Pieces placed before the game ended
+    pub placements: usize,
+    /// Moves that left a fork, as in `Game::fork_moves`
+    pub forks: usize,
+}
+
+/// Games played out between engine agents in fixed seats
+pub struct Simulation {
+    pub games: Vec<SimulatedGame>,
+}
+
+/// Play `games` games in parallel, with fresh agents each game from
+/// `agents`, given the seat and a seed. The same `seed` plays the same
+/// games. Fails with a move an agent makes that the rules don't allow.
+pub fn simulate<F>(
+    agents: F,
+    games: usize,
+    seed: u64,
+    rules: Rules,
+) -> Result<Simulation, MoveError>
+where
+    F: Fn(Player, u64) -> Box<dyn Agent> + Sync,
+{
+    let games = (0..games as u64)
+        .into_par_iter()
+        .map(|game_idx| {
+            let seed = seed.wrapping_add(game_idx.wrapping_mul(2));
+            let mut seats = [
+                agents(Player::A, seed),
+                agents(Player::B, seed.wrapping_add(1)),
+            ];
+            let mut game = Game::with_rules(rules);
+
+            let resolution = loop {
+                if let Some(resolution) = game.resolution() {
+                    break resolution;
+                }
+                game.tick(&mut seats)?;
+            };
+
+            Ok(SimulatedGame {
+                seed,
+                winner: resolution.winner(),
+                placements: game
+                    .history()
+                    .iter()
+                    .filter(|(_, mv)| matches!(mv, Move::Place(_)))
+                    .count(),
+                forks: game.fork_moves().len(),
+            })
+        })
+        .collect::<Result<_, MoveError>>()?;
+
+    Ok(Simulation { games })
+}
+
+impl Simulation {
+    fn count(&self, winner: Option<Player>) -> usize {
+        self.games
+            .iter()
+            .filter(|game| game.winner == winner)
+            .count()
+    }
+
+    /// Player A's score, 1 for a win and 0.5 for a draw, averaged over the
+    /// games. Above 0.5 means staging first is an advantage.
+    pub fn first_stager_score(&self) -> f64 {
+        let points = self.count(Some(Player::A)) as f64 + self.count(None) as f64 / 2.0;
+
+        points / self.games.len().max(1) as f64
+    }
+
+    /// Mean number of pieces placed per game
+    pub fn average_length(&self) -> f64 {
+        let placements: usize = self.games.iter().map(|game| game.placements).sum();
+
+        placements as f64 / self.games.len().max(1) as f64
+    }
+
+    /// Share of games in which some move left a fork
+    pub fn fork_rate(&self) -> f64 {
+        let forked = self.games.iter().filter(|game| game.forks > 0).count();
+
+        forked as f64 / self.games.len().max(1) as f64
+    }
+
+    /// One line per game under a header line
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("seed,winner,placements,forks\n");
+        for game in &self.games {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                game.seed,
+                winner_name(game.winner),
+                game.placements,
+                game.forks
+            ));
+        }
+
+        csv
+    }
+
+    /// The statistics and every game, as a JSON object
+    pub fn to_json(&self) -> String {
+        let games: Vec<String> = self
+            .games
+            .iter()
+            .map(|game| {
+                format!(
+                    "{{\"seed\":{},\"winner\":\"{}\",\"placements\":{},\"forks\":{}}}",
+                    game.seed,
+                    winner_name(game.winner),
+                    game.placements,
+                    game.forks
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"games\":{},\"a_wins\":{},\"b_wins\":{},\"draws\":{},\
+             \"first_stager_score\":{:.4},\"average_length\":{:.4},\"fork_rate\":{:.4},\
+             \"results\":[{}]}}\n",
+            self.games.len(),
+            self.count(Some(Player::A)),
+            self.count(Some(Player::B)),
+            self.count(None),
+            self.first_stager_score(),
+            self.average_length(),
+            self.fork_rate(),
+            games.join(",")
+        )
+    }
+}
+
+fn winner_name(winner: Option<Player>) -> &'static str {
+    match winner {
+        Some(Player::A) => "A",
+        Some(Player::B) => "B",
+        None => "draw",
+    }
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} games: Player A {} wins, Player B {} wins, {} draws",
+            self.games.len(),
+            self.count(Some(Player::A)),
+            self.count(Some(Player::B)),
+            self.count(None)
+        )?;
+        writeln!(f, "first stager scores {:.3}", self.first_stager_score())?;
+        writeln!(f, "{:.1} pieces placed per game", self.average_length())?;
+        write!(f, "{:.1}% of games had a fork", self.fork_rate() * 100.0)
+    }
+}
+
+/// A best-of-`games` match between two sides, 0 and 1, who take turns
+/// staging first, starting with side 0
+pub struct Match {
+    games: usize,
+    wins: [usize; 2],
+    draws: usize,
+    game: Game,
+}
+
+impl Match {
+    /// Start a match whose games are all played under `rules`
+    pub fn new(games: usize, rules: Rules) -> Self {
+        Match {
+            games,
+            wins: [0; 2],
+            draws: 0,
+            game: Game::with_rules(rules),
+        }
+    }
+
+    /// The game being played
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn game_mut(&mut self) -> &mut Game {
+        &mut self.game
+    }
+
+    /// Wins for each side
+    pub fn wins(&self) -> [usize; 2] {
+        self.wins
+    }
+
+    pub fn draws(&self) -> usize {
+        self.draws
+    }
+
+    /// Games finished so far
+    pub fn played(&self) -> usize {
+        self.wins[0] + self.wins[1] + self.draws
+    }
+
+    /// The side playing as `player` in the current game
+    pub fn side(&self, player: Player) -> usize {
+        (player.index() + self.played()) % 2
+    }
+
+    /// Whether every game has been played, or one side is ahead by more
+    /// than the games left
+    pub fn is_over(&self) -> bool {
+        let lead = self.wins[0].max(self.wins[1]) - self.wins[0].min(self.wins[1]);
+
+        lead > self.games - self.played().min(self.games)
+    }
+
+    /// The side with more wins, if either has
+    pub fn leader(&self) -> Option<usize> {
+        match self.wins[0].cmp(&self.wins[1]) {
+            Ordering::Greater => Some(0),
+            Ordering::Less => Some(1),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// Count the current game if it's over and, unless that decides the
+    /// match, set up the next one. Returns whether there's a game to play.
+    pub fn next_game(&mut self) -> bool {
+        if let Some(resolution) = self.game.resolution() {
+            match resolution.winner() {
+                Some(player) => self.wins[self.side(player)] += 1,
+                None => self.draws += 1,
+            }
+            if !self.is_over() {
+                self.game = Game::with_rules(self.game.rules());
+            }
+        }
+
+        !self.is_over()
+    }
+
+    /// The score so far, with `names` for the two sides
+    pub fn scoreboard(&self, names: [&str; 2]) -> String {
+        format!(
+            "{} {}, {} {}, {} drawn, after {} of {} games",
+            names[0],
+            self.wins[0],
+            names[1],
+            self.wins[1],
+            self.draws,
+            self.played(),
+            self.games
+        )
+    }
+}
+
+impl Report {
+    /// Mean time each agent spent per half-turn
+    fn average_move_time(&self, agent_idx: usize) -> Duration {
+        match self.moves[agent_idx] {
+            0 => Duration::default(),
+            moves => self.think_time[agent_idx] / moves,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let estimate = Estimate::from_samples(&self.scores);
+
+        writeln!(
+            f,
+            "{} wins, {} losses, {} draws",
+            self.wins, self.losses, self.draws
+        )?;
+        writeln!(
+            f,
+            "score {:.3} \u{b1} {:.3} (95% confidence)",
+            estimate.mean,
+            estimate.margin()
+        )?;
+        write!(
+            f,
+            "average move time {:?} vs {:?}",
+            self.average_move_time(0),
+            self.average_move_time(1)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::RandomAgent;
+    use crate::{Coord, Piece};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn tournament_counts_every_game_test() {
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            }),
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(2),
+            }),
+        ];
+
+        let report = run(&mut agents, 6).unwrap();
+
+        assert_eq!(report.wins + report.losses + report.draws, 6);
+        assert_eq!(report.scores.len(), 6);
+        // Each agent stages at least once per game
+        assert!(report.moves.iter().all(|moves| *moves >= 6));
+    }
+
+    /// Always places in the corner, taken or not
+    struct CornerAgent(RandomAgent);
+
+    impl Agent for CornerAgent {
+        fn choose_placement(&mut self, _game: &Game, _piece: &Piece) -> Option<Coord> {
+            Some((0, 0))
+        }
+
+        fn choose_stage(&mut self, game: &Game) -> Option<Piece> {
+            self.0.choose_stage(game)
+        }
+    }
+
+    #[test]
+    fn illegal_moves_end_tournament_test() {
+        let mut agents: [Box<dyn Agent>; 2] = [
+            Box::new(CornerAgent(RandomAgent {
+                rng: StdRng::seed_from_u64(1),
+            })),
+            Box::new(CornerAgent(RandomAgent {
+                rng: StdRng::seed_from_u64(2),
+            })),
+        ];
+
+        assert_eq!(run(&mut agents, 2).err(), Some(MoveError::Occupied((0, 0))));
+    }
+
+    #[test]
+    fn simulation_test() {
+        let random = |_, seed| {
+            Box::new(RandomAgent {
+                rng: StdRng::seed_from_u64(seed),
+            }) as Box<dyn Agent>
+        };
+
+        let simulation = simulate(random, 20, 7, Rules::default()).unwrap();
+        assert_eq!(simulation.games.len(), 20);
+        assert!(simulation
+            .games
+            .iter()
+            .all(|game| (4..=16).contains(&game.placements)));
+        assert!((0.0..=1.0).contains(&simulation.first_stager_score()));
+
+        // Games don't depend on how rayon schedules them
+        let again = simulate(random, 20, 7, Rules::default()).unwrap();
+        assert_eq!(again.games, simulation.games);
+
+        assert_eq!(simulation.to_csv().lines().count(), 21);
+        assert!(simulation.to_json().contains("\"games\":20,"));
+    }
+
+    #[test]
+    fn match_keeps_score_test() {
+        let mut best_of = Match::new(3, Rules::default());
+        assert_eq!(best_of.side(Player::A), 0);
+
+        // Side 0 resigns as Player A while staging
+        best_of.game_mut().resign();
+        assert!(best_of.next_game());
+        assert_eq!(best_of.wins(), [0, 1]);
+        assert_eq!(best_of.side(Player::A), 1);
+        assert_eq!(best_of.game().resolution(), None);
+
+        // An unfinished game doesn't count
+        assert!(best_of.next_game());
+        assert_eq!(best_of.played(), 1);
+
+        // Side 1 resigns as Player A, leaving the decider
+        best_of.game_mut().resign();
+        assert!(best_of.next_game());
+        assert_eq!(best_of.wins(), [1, 1]);
+        assert_eq!(best_of.leader(), None);
+
+        best_of.game_mut().resign();
+        assert!(!best_of.next_game());
+        assert_eq!(best_of.leader(), Some(1));
+        assert_eq!(
+            best_of.scoreboard(["human", "random"]),
+            "human 1, random 2, 0 drawn, after 3 of 3 games"
+        );
+    }
+
+    #[test]
+    fn match_ends_early_test() {
+        let mut best_of = Match::new(3, Rules::default());
+        best_of.game_mut().resign();
+        assert!(best_of.next_game());
+
+        // Side 0 is Player B now, and resigns once Player A stages
+        let game = best_of.game_mut();
+        let piece = game.pieces()[0].clone();
+        game.stage(&piece).unwrap();
+        game.resign();
+        assert!(!best_of.next_game());
+        assert_eq!(best_of.wins(), [0, 2]);
+        assert_eq!(best_of.played(), 2);
+    }
+}
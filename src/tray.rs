@@ -0,0 +1,122 @@
+//! How the unplayed pieces are laid out for the person playing. The pool
+//! in `Game` stays in index order for the engines, while a `Tray` groups
+//! the pieces by an attribute, all the tall ones together, or narrows them
+//! to the ones with a single value, to help judge which are safe to hand
+//! over.
+
+use crate::{Attribute, Piece};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tray {
+    /// Attribute to group the pieces by, keeping index order within each
+    /// group
+    pub group: Option<Attribute>,
+    /// Show only the pieces with one value of an attribute, as the
+    /// attribute and an index into its `Attribute::values`
+    pub filter: Option<(Attribute, usize)>,
+}
+
+impl Tray {
+    /// Lay `pieces` out as groups to show in order, each headed by the
+    /// value its pieces share when grouping. Groups left empty by the
+    /// filter are dropped.
+    pub fn arrange(&self, pieces: &[Piece]) -> Vec<(Option<&'static str>, Vec<Piece>)> {
+        let shown: Vec<&Piece> = pieces
+            .iter()
+            .filter(|piece| match self.filter {
+                Some((attribute, value)) => piece.value(attribute) == value,
+                None => true,
+            })
+            .collect();
+
+        let groups = match self.group {
+            Some(attribute) => (0..2)
+                .map(|value| {
+                    let members = shown
+                        .iter()
+                        .filter(|piece| piece.value(attribute) == value)
+                        .map(|piece| (*piece).clone())
+                        .collect();
+                    (Some(attribute.values()[value]), members)
+                })
+                .collect(),
+            None => vec![(None, shown.into_iter().cloned().collect())],
+        };
+
+        groups
+            .into_iter()
+            .filter(|(_, members): &(_, Vec<Piece>)| !members.is_empty())
+            .collect()
+    }
+
+    /// Apply a tray command: `group <attribute>` or `group none`, and
+    /// `only <value>` or `only all`
+    pub fn command(&mut self, text: &str) -> Result<(), String> {
+        let mut words = text.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("group"), Some("none"), None) => self.group = None,
+            (Some("group"), Some(name), None) => {
+                self.group = Some(Attribute::parse(name).ok_or_else(|| {
+                    format!(
+                        "Unknown attribute {}, expected height, color, density or shape",
+                        name
+                    )
+                })?)
+            }
+            (Some("only"), Some("all"), None) => self.filter = None,
+            (Some("only"), Some(name), None) => {
+                self.filter = Some(Attribute::parse_value(name).ok_or_else(|| {
+                    format!("Unknown value {}, expected one like tall or hollow", name)
+                })?)
+            }
+            _ => return Err("Expected group <attribute|none> or only <value|all>".to_string()),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::all_pieces;
+
+    fn indices(pieces: &[Piece]) -> Vec<u8> {
+        pieces.iter().map(Piece::index).collect()
+    }
+
+    #[test]
+    fn arrange_test() {
+        let pieces: Vec<Piece> = all_pieces().into_iter().step_by(3).collect();
+        let mut tray = Tray::default();
+        let plain = tray.arrange(&pieces);
+        assert_eq!(plain.len(), 1);
+        assert_eq!(indices(&plain[0].1), [0, 3, 6, 9, 0xc, 0xf]);
+
+        tray.command("group density").unwrap();
+        let grouped = tray.arrange(&pieces);
+        assert_eq!(grouped[0].0, Some("solid"));
+        assert_eq!(indices(&grouped[0].1), [0, 9, 0xc]);
+        assert_eq!(grouped[1].0, Some("hollow"));
+        assert_eq!(indices(&grouped[1].1), [3, 6, 0xf]);
+
+        tray.command("only short").unwrap();
+        let filtered = tray.arrange(&pieces);
+        assert_eq!(indices(&filtered[0].1), [9, 0xc]);
+        assert_eq!(indices(&filtered[1].1), [0xf]);
+
+        // A group the filter empties isn't shown
+        tray.command("only round").unwrap();
+        tray.command("group shape").unwrap();
+        let filtered = tray.arrange(&pieces);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].0, Some("round"));
+
+        tray.command("group none").unwrap();
+        tray.command("only all").unwrap();
+        assert_eq!(tray, Tray::default());
+        assert!(tray.command("group size").is_err());
+        assert!(tray.command("only blue").is_err());
+        assert!(tray.command("only").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! A line-based text protocol for driving the engine from another program,
+//! in the spirit of UCI for chess.
+//!
+//! Commands, one per line:
+//!
+//! - `uqi`: answered with `id name quarto` and `uqiok`
+//! - `isready`: answered with `readyok`
+//! - `newgame`: start again from an empty board with Player A to stage
+//! - `position <board> <a|b> [piece]`: set up a position in the notation
+//!   of the `position` module, with the player to act and, if they must
+//!   place, the piece staged for them
+//! - `move <square|piece>`: apply a placement like `b3` or a stage like `7`
+//! - `go [movetime <ms>]`: search the current position and answer with
+//!   `bestmove <square|piece>`, or `bestmove resign`
+//! - `quit`
+//!
+//! Anything that can't be carried out is answered with `error <reason>`.
+
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::agent::{Agent, MonteCarloAgent};
+use crate::game::{square_name, Game, Phase, Rules};
+use crate::position::parse_position;
+use crate::{unplayed_pieces, Piece, Player};
+
+/// Playouts used by `go` without a time limit
+const DEFAULT_PLAYOUTS: usize = 200;
+
+/// Most playouts `go movetime` lets the agent search with, however long
+/// it's given
+const MAX_PLAYOUTS: usize = 1 << 20;
+
+/// Answer commands from `input` until it ends or asks to quit
+pub fn run(input: impl BufRead, mut output: impl Write, seed: u64) -> io::Result<()> {
+    let mut game = Game::new();
+    let mut agent = MonteCarloAgent {
+        playouts: DEFAULT_PLAYOUTS,
+        time_budget: None,
+        analysis: None,
+        cancel: None,
+        rng: StdRng::seed_from_u64(seed),
+    };
+
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            [] => {}
+            ["uqi"] => {
+                writeln!(output, "id name quarto")?;
+                writeln!(output, "uqiok")?;
+            }
+            ["isready"] => writeln!(output, "readyok")?,
+            ["newgame"] => game = Game::new(),
+            ["position", args @ ..] => match parse_game(args) {
+                Ok(position) => game = position,
+                Err(err) => writeln!(output, "error {}", err)?,
+            },
+            ["move", choice] => {
+                if let Err(err) = apply_choice(&mut game, choice) {
+                    writeln!(output, "error {}", err)?;
+                }
+            }
+            ["go"] => {
+                agent.playouts = DEFAULT_PLAYOUTS;
+                writeln!(output, "{}", best_move(&game, &mut agent))?;
+            }
+            ["go", "movetime", millis] => match millis.parse() {
+                Ok(millis) => {
+                    agent.playouts = MAX_PLAYOUTS;
+                    agent.set_time_budget(Duration::from_millis(millis));
+                    writeln!(output, "{}", best_move(&game, &mut agent))?;
+                }
+                Err(_) => writeln!(output, "error expected a number of milliseconds")?,
+            },
+            ["quit"] => break,
+            _ => writeln!(output, "error unknown command {}", line.trim())?,
+        }
+
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Build a game from the arguments of a `position` command
+fn parse_game(args: &[&str]) -> Result<Game, String> {
+    let (board, player, staged) = match args {
+        [board, player] => (board, player, None),
+        [board, player, staged] => (board, player, Some(staged)),
+        _ => return Err("expected position <board> <a|b> [piece]".to_string()),
+    };
+
+    let board = parse_position(board).map_err(|err| err.to_string())?;
+    let player = match *player {
+        "a" => Player::A,
+        "b" => Player::B,
+        _ => return Err(format!("unknown player {}", player)),
+    };
+    let staged = match staged {
+        Some(staged) => Some(Piece::parse(staged).ok_or(format!("unknown piece {}", staged))?),
+        None => None,
+    };
+
+    let mut pieces = unplayed_pieces(&board);
+    let phase = match staged {
+        Some(piece) => {
+            if !pieces.contains(&piece) {
+                return Err(format!("{:x} is already on the board", piece.index()));
+            }
+            pieces.retain(|p| *p != piece);
+            Phase::AwaitingPlacement(player, piece)
+        }
+        None => Phase::AwaitingStage(player),
+    };
+
+    Game::from_position(board, pieces, phase, Rules::default()).map_err(|err| err.to_string())
+}
+
+/// Apply a `move` argument, a square while placing or a piece while staging
+fn apply_choice(game: &mut Game, choice: &str) -> Result<(), String> {
+    let mv = game.parse_move(choice).ok_or_else(|| match game.phase() {
+        Phase::AwaitingPlacement(..) => format!("expected a square, got {}", choice),
+        Phase::AwaitingStage(_) => format!("expected a piece, got {}", choice),
+        Phase::GameOver(_) => "the game is over".to_string(),
+    })?;
+
+    game.apply(mv).map_err(|err| err.to_string())
+}
+
+/// The agent's choice for the current phase, as a `bestmove` answer
+fn best_move(game: &Game, agent: &mut impl Agent) -> String {
+    let choice = match game.phase() {
+        Phase::AwaitingPlacement(_, piece) => agent
+            .choose_placement(game, piece)
+            .map(|(row_idx, square_idx)| square_name(row_idx, square_idx)),
+        Phase::AwaitingStage(_) => agent
+            .choose_stage(game)
+            .map(|piece| format!("{:x}", piece.index())),
+        Phase::GameOver(_) => return "error the game is over".to_string(),
+    };
+
+    format!(
+        "bestmove {}",
+        choice.unwrap_or_else(|| "resign".to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn session(commands: &str) -> String {
+        let mut output = Vec::new();
+        run(commands.as_bytes(), &mut output, 1).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn handshake_and_search_test() {
+        let output = session("uqi\nisready\nposition 014./..../..../.... b 5\ngo\nquit\ngo\n");
+
+        assert_eq!(output, "id name quarto\nuqiok\nreadyok\nbestmove d1\n");
+    }
+
+    #[test]
+    fn movetime_answers_quickly_when_nothing_to_search_test() {
+        // The quarto on d1 ends the game
+        let output = session("position 014./..../..../.... b 5\nmove d1\ngo movetime 2000\n");
+        assert_eq!(output, "error the game is over\n");
+
+        let started = Instant::now();
+        let output = session("position 0f69/a.5./3c.e/.2b. a 1\ngo movetime 3000\n");
+        assert!(output.starts_with("bestmove "));
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn moves_and_errors_test() {
+        let output = session(
+            "move b3\nmove 5\nmove 5\nmove a1\nmove 0\nmove a1\nposition 0/1 a\nposition 0.../..../..../.... a 0\nfly\n",
+        );
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                "error expected a piece, got b3",
+                "error expected a square, got 5",
+                "error a1 is already taken",
+                "error expected 4 rows, found 2",
+                "error 0 is already on the board",
+                "error unknown command fly",
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Cross-checks the line-based win detection against a brute force
+//! checker built on piece index bits, over every combination of four
+//! pieces on every line.
+
+use crate::{all_pieces, empty_board, find_quarto, winning_square, Board, Line, Piece};
+
+/// Whole-board check that doesn't share any code with `shared_attribute`:
+/// a line wins when it is full and some bit of the piece indexes is the
+/// same for all four pieces
+fn brute_force_win(board: &Board) -> bool {
+    Line::all().iter().any(|line| {
+        let indexes: Vec<u8> = line
+            .squares()
+            .iter()
+            .filter_map(|(row_idx, square_idx)| board[*row_idx][*square_idx].as_ref())
+            .map(Piece::index)
+            .collect();
+
+        indexes.len() == 4
+            && (0..4).any(|bit| {
+                indexes
+                    .iter()
+                    .all(|index| index & (1 << bit) == indexes[0] & (1 << bit))
+            })
+    })
+}
+
+/// Every set of four distinct pieces, in index order
+fn combinations() -> Vec<[Piece; 4]> {
+    let pieces = all_pieces();
+    let mut combinations = Vec::new();
+
+    for a in 0..16 {
+        for b in a + 1..16 {
+            for c in b + 1..16 {
+                for d in c + 1..16 {
+                    combinations.push([
+                        pieces[a].clone(),
+                        pieces[b].clone(),
+                        pieces[c].clone(),
+                        pieces[d].clone(),
+                    ]);
+                }
+            }
+        }
+    }
+
+    combinations
+}
+
+#[test]
+fn full_lines_agree_test() {
+    for line in Line::all().iter() {
+        for combination in combinations() {
+            let mut board = empty_board();
+            for ((row_idx, square_idx), piece) in line.squares().iter().zip(combination.iter()) {
+                board[*row_idx][*square_idx] = Some(piece.clone());
+            }
+
+            let expected = brute_force_win(&board);
+            assert_eq!(find_quarto(&board).is_some(), expected, "{:?}", line);
+            for (row_idx, square_idx) in line.squares().iter() {
+                assert_eq!(
+                    winning_square(&board, *row_idx, *square_idx),
+                    expected,
+                    "{:?} at ({}, {})",
+                    line,
+                    row_idx,
+                    square_idx
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn incomplete_lines_never_win_test() {
+    for line in Line::all().iter() {
+        for combination in combinations() {
+            for gap in 0..4 {
+                let mut board = empty_board();
+                for (idx, ((row_idx, square_idx), piece)) in
+                    line.squares().iter().zip(combination.iter()).enumerate()
+                {
+                    if idx != gap {
+                        board[*row_idx][*square_idx] = Some(piece.clone());
+                    }
+                }
+
+                assert!(!brute_force_win(&board));
+                assert!(find_quarto(&board).is_none(), "{:?} gap {}", line, gap);
+                for (row_idx, square_idx) in line.squares().iter() {
+                    assert!(!winning_square(&board, *row_idx, *square_idx));
+                }
+            }
+        }
+    }
+}